@@ -0,0 +1,201 @@
+extern crate bytes;
+use bytes::BytesMut;
+
+extern crate futures;
+use futures::sync::mpsc::UnboundedSender;
+
+extern crate pcs_protocol;
+use pcs_protocol::{ MsgType, SerDe };
+
+extern crate tokio_io;
+use tokio_io::codec::{ Decoder, Encoder };
+
+use std::cmp;
+use std::io::{ self, Cursor };
+
+// Test data can be large, but anything past this is more likely garbage than a real message
+pub const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+const HEADER: usize = 4;
+
+// A message, or a frame that couldn't be decoded. Corrupt frames are skipped over
+// using their length prefix, so they don't take the rest of the connection down with them.
+pub enum Frame {
+    Msg(MsgType),
+    Corrupt(String)
+}
+
+// Frames every message as a big-endian u32 length followed by the serialized message
+pub struct MsgCodec {
+    max_frame:  usize,
+    // Bytes still to throw away from an oversized frame
+    skip:       usize
+}
+impl MsgCodec {
+    pub fn new(max_frame: usize) -> MsgCodec {
+        MsgCodec {
+            max_frame:  max_frame,
+            skip:       0
+        }
+    }
+}
+impl Default for MsgCodec {
+    fn default() -> MsgCodec {
+        MsgCodec::new(MAX_FRAME)
+    }
+}
+
+fn encode(msg: &MsgType) -> Result<Vec<u8>, io::Error> {
+    let mut body = Vec::new();
+    msg.serialize(&mut body)?;
+    if body.len() > u32::max_value() as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Message too large to frame"));
+    }
+    let len = body.len() as u32;
+    let mut frame = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+    frame.append(&mut body);
+    Ok(frame)
+}
+
+// Messages waiting to be written to the server. A `FramedWrite` driven by the reactor writes
+// them as the socket allows, so a slow server never blocks us or cuts a frame short.
+pub type Outgoing = UnboundedSender<MsgType>;
+
+pub fn queue(out: &Outgoing, msg: MsgType) -> Result<(), io::Error> {
+    out.unbounded_send(msg).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Connection to the server is closed"))
+}
+
+impl Decoder for MsgCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        if self.skip > 0 {
+            let n = cmp::min(self.skip, buf.len());
+            buf.split_to(n);
+            self.skip -= n;
+            if self.skip > 0 {
+                return Ok(None);
+            }
+        }
+        if buf.len() < HEADER {
+            return Ok(None);
+        }
+        let len = buf[..HEADER].iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+        if len > self.max_frame {
+            buf.split_to(HEADER);
+            self.skip = len;
+            return Ok(Some(Frame::Corrupt(format!("Frame of {} bytes is over the {} byte limit", len, self.max_frame))));
+        }
+        if buf.len() < HEADER + len {
+            // Wait for the rest of the frame
+            let needed = HEADER + len - buf.len();
+            buf.reserve(needed);
+            return Ok(None);
+        }
+        buf.split_to(HEADER);
+        let body = buf.split_to(len);
+        let mut cursor = Cursor::new(&body[..]);
+        Ok(Some(match MsgType::deserialize(&mut cursor) {
+            Ok(ref msg) if cursor.position() as usize != len => {
+                Frame::Corrupt(format!("{} trailing bytes after {:?}", len - cursor.position() as usize, msg))
+            },
+            Ok(msg) => Frame::Msg(msg),
+            Err(e) => Frame::Corrupt(format!("Couldn't decode frame of {} bytes: {}", len, e))
+        }))
+    }
+}
+
+impl Encoder for MsgCodec {
+    type Item = MsgType;
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: MsgType, buf: &mut BytesMut) -> Result<(), io::Error> {
+        let frame = encode(&msg)?;
+        buf.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(frame: &Frame) -> String {
+        match *frame {
+            Frame::Msg(MsgType::Ping) => "ping".to_owned(),
+            Frame::Msg(MsgType::Error(ref e)) => format!("error {}", e),
+            Frame::Msg(ref msg) => format!("{:?}", msg),
+            Frame::Corrupt(_) => "corrupt".to_owned()
+        }
+    }
+
+    // Everything that can be decoded from what's been read so far
+    fn decode(codec: &mut MsgCodec, buf: &mut BytesMut) -> Vec<String> {
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(buf).unwrap() {
+            frames.push(describe(&frame));
+        }
+        frames
+    }
+
+    fn ping() -> Vec<u8> {
+        encode(&MsgType::Ping).unwrap()
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_header() {
+        let frame = ping();
+        let mut codec = MsgCodec::default();
+        let mut buf = BytesMut::from(&frame[..2]);
+        assert!(decode(&mut codec, &mut buf).is_empty());
+        buf.extend_from_slice(&frame[2..]);
+        assert_eq!(decode(&mut codec, &mut buf), vec!["ping"]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_body() {
+        let frame = encode(&MsgType::Error("split".to_owned())).unwrap();
+        let mut codec = MsgCodec::default();
+        let mut buf = BytesMut::from(&frame[..HEADER + 1]);
+        assert!(decode(&mut codec, &mut buf).is_empty());
+        buf.extend_from_slice(&frame[HEADER + 1..]);
+        assert_eq!(decode(&mut codec, &mut buf), vec!["error split"]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decodes_frames_read_together() {
+        let mut frames = ping();
+        frames.extend(encode(&MsgType::Error("second".to_owned())).unwrap());
+        let mut codec = MsgCodec::default();
+        let mut buf = BytesMut::from(&frames[..]);
+        assert_eq!(decode(&mut codec, &mut buf), vec!["ping", "error second"]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn skips_oversized_frames() {
+        let mut codec = MsgCodec::new(16);
+        let mut buf = BytesMut::from(&[0u8, 0, 0, 100][..]);
+        buf.extend_from_slice(&[0xaa; 60]);
+        assert_eq!(decode(&mut codec, &mut buf), vec!["corrupt"]);
+        assert!(buf.is_empty());
+        // The rest of the oversized frame arrives along with the next one
+        buf.extend_from_slice(&[0xaa; 40]);
+        buf.extend_from_slice(&ping());
+        assert_eq!(decode(&mut codec, &mut buf), vec!["ping"]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn skips_corrupt_frames() {
+        let mut frames = vec![0u8, 0, 0, 3, 0xff, 0xff, 0xff];
+        frames.extend(ping());
+        let mut codec = MsgCodec::default();
+        let mut buf = BytesMut::from(&frames[..]);
+        assert_eq!(decode(&mut codec, &mut buf), vec!["corrupt", "ping"]);
+        assert!(buf.is_empty());
+    }
+}
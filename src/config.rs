@@ -0,0 +1,249 @@
+extern crate clap;
+use clap::ArgMatches;
+
+extern crate serde_yaml;
+
+extern crate webpki;
+
+use std::{ env, fmt, fs };
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::error::JudgeError;
+use super::{ executor, handshake, judge, ssl };
+
+// What can be set in the YAML file given to --config. The keys are the long command line flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct File {
+    host:           Option<String>,
+    domain:         Option<String>,
+    port:           Option<u16>,
+    certificate:    Option<Vec<String>>,
+    system_roots:   Option<bool>,
+    pin:            Option<Vec<String>>,
+    client_cert:    Option<String>,
+    client_key:     Option<String>,
+    name:           Option<String>,
+    heartbeat:      Option<u64>,
+    server_timeout: Option<u64>,
+    executors:      Option<String>,
+    sandbox:        Option<String>,
+    wall_multiplier: Option<f64>,
+    default_wall:   Option<u64>,
+    workers:        Option<usize>,
+    work_dir:       Option<String>,
+    problems:       Option<String>,
+    store:          Option<String>,
+    store_budget:   Option<u64>,
+    no_jail:        Option<bool>,
+    jail_mounts:    Option<Vec<String>>
+}
+impl File {
+    pub fn from_file(path: &str) -> Result<File, String> {
+        let file = fs::File::open(path).map_err(|e| format!("Couldn't open config {}: {}", path, e))?;
+        serde_yaml::from_reader(file).map_err(|e| format!("Bad config {}: {}", path, e))
+    }
+}
+
+// Everything the judge needs to start, after merging all the sources
+pub struct Options {
+    // Looked up again for every connection, as the server's address can change while we run
+    pub host:           String,
+    pub port:           u16,
+    pub domain:         String,
+    pub trust:          ssl::Trust,
+    pub client_auth:    Option<(String, String)>,
+    pub name:           String,
+    pub heartbeat:      Duration,
+    pub server_timeout: Duration,
+    pub settings:       judge::Settings
+}
+
+// Each setting comes from the first of the command line, a PCS_JUDGE_<ARG> environment
+// variable, the config file and finally the default. Problems are collected rather than
+// returned, so they can all be reported at once. Args have to be named after their long
+// option, with `_` for `-`, for the variable and the messages to match it.
+struct Sources<'a, 'b: 'a> {
+    matches:    &'a ArgMatches<'b>,
+    problems:   Vec<String>
+}
+impl<'a, 'b> Sources<'a, 'b> {
+    fn var(arg: &str) -> String {
+        format!("PCS_JUDGE_{}", arg.to_uppercase())
+    }
+    // The value and where it came from, if it was given on the command line or in the environment
+    fn given(&self, arg: &str) -> Option<(String, String)> {
+        if let Some(value) = self.matches.value_of(arg) {
+            return Some((format!("--{}", arg.replace('_', "-")), value.to_owned()));
+        }
+        env::var(Self::var(arg)).ok().map(|value| (Self::var(arg), value))
+    }
+    fn optional<T: FromStr>(&mut self, arg: &str, file: Option<T>) -> Option<T> where T::Err: fmt::Display {
+        match self.given(arg) {
+            Some((source, value)) => match value.parse() {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    self.problems.push(format!("{}: couldn't parse {:?}: {}", source, value, e));
+                    None
+                }
+            },
+            None => file
+        }
+    }
+    fn value<T: FromStr>(&mut self, arg: &str, file: Option<T>, default: T) -> T where T::Err: fmt::Display {
+        self.optional(arg, file).unwrap_or(default)
+    }
+    // Lists are separated by commas in the environment
+    fn list(&mut self, arg: &str, file: Option<Vec<String>>, default: &[&str]) -> Vec<String> {
+        if let Some(values) = self.matches.values_of(arg) {
+            return values.map(String::from).collect();
+        }
+        if let Ok(value) = env::var(Self::var(arg)) {
+            return value.split(',').filter(|v| !v.is_empty()).map(String::from).collect();
+        }
+        file.unwrap_or_else(|| default.iter().map(|&v| v.to_owned()).collect())
+    }
+    fn flag(&mut self, arg: &str, file: Option<bool>) -> bool {
+        if self.matches.is_present(arg) {
+            return true;
+        }
+        self.value(arg, file, false)
+    }
+    fn check(&mut self, ok: bool, problem: String) {
+        if !ok {
+            self.problems.push(problem);
+        }
+    }
+}
+
+fn open<'a, 'b>(matches: &'a ArgMatches<'b>) -> (Sources<'a, 'b>, File) {
+    let mut sources = Sources { matches: matches, problems: Vec::new() };
+    let file = match sources.given("config") {
+        Some((_, path)) => File::from_file(&path).unwrap_or_else(|e| {
+            sources.problems.push(e);
+            File::default()
+        }),
+        None => File::default()
+    };
+    (sources, file)
+}
+
+fn settings(sources: &mut Sources, file: &mut File) -> judge::Settings {
+    let executors = sources.value("executors", file.executors.take(), "executors/".to_owned());
+    if !Path::new(&executors).is_dir() {
+        sources.problems.push(format!("Executors directory {} doesn't exist", executors));
+    } else if executor::languages(Path::new(&executors)).is_empty() {
+        sources.problems.push(format!("No usable executors in {}", executors));
+    }
+    let sandbox = sources.value("sandbox", file.sandbox.take(), "seccomp".to_owned());
+    let sandbox: judge::Sandbox = sandbox.parse().unwrap_or_else(|e| {
+        sources.problems.push(e);
+        judge::Sandbox::SeccompTrace
+    });
+    let wall_multiplier = sources.value("wall_multiplier", file.wall_multiplier, 3.0);
+    sources.check(wall_multiplier >= 1.0, format!("wall-multiplier ({}) can't be less than 1", wall_multiplier));
+    let default_wall = sources.value("default_wall", file.default_wall, 30);
+    sources.check(default_wall > 0, "default-wall has to be at least 1 second".to_owned());
+    let workers = sources.optional("workers", file.workers).unwrap_or_else(judge::physical_cores);
+    sources.check(workers > 0, "workers has to be at least 1".to_owned());
+    let work_dir = sources.value("work_dir", file.work_dir.take(), "work/".to_owned());
+    sources.check(!Path::new(&work_dir).is_file(), format!("Work directory {} is a file", work_dir));
+    let problems = sources.value("problems", file.problems.take(), "problems/".to_owned());
+    sources.check(!Path::new(&problems).is_file(), format!("Problems directory {} is a file", problems));
+    let store = sources.value("store", file.store.take(), "store/".to_owned());
+    sources.check(!Path::new(&store).is_file(), format!("Store directory {} is a file", store));
+    let store_budget = sources.value("store_budget", file.store_budget, 4096);
+    sources.check(store_budget > 0, "store-budget has to be at least 1 MiB".to_owned());
+    let jail = if sources.flag("no_jail", file.no_jail) {
+        warn!("Running without the jail, so compilers and submissions can read anything the judge can");
+        None
+    } else {
+        Some(sources.list("jail_mounts", file.jail_mounts.take(), judge::JAIL_MOUNTS))
+    };
+    judge::Settings {
+        executors:      executors,
+        sandbox:        sandbox,
+        wall_multiplier: wall_multiplier,
+        default_wall:   Duration::from_secs(default_wall),
+        workers:        workers,
+        work_dir:       work_dir,
+        problems:       problems,
+        store:          store,
+        store_budget:   store_budget * 1024 * 1024,
+        jail:           jail
+    }
+}
+
+// Just what's needed to run submissions, without anything about the server
+pub fn load_settings(matches: &ArgMatches) -> Result<judge::Settings, JudgeError> {
+    let (mut sources, mut file) = open(matches);
+    let settings = settings(&mut sources, &mut file);
+    if sources.problems.is_empty() {
+        Ok(settings)
+    } else {
+        Err(JudgeError::Config(sources.problems))
+    }
+}
+
+pub fn load(matches: &ArgMatches) -> Result<Options, JudgeError> {
+    let (mut sources, mut file) = open(matches);
+    let settings = settings(&mut sources, &mut file);
+
+    let host = sources.value("host", file.host, "localhost".to_owned());
+    let port = sources.value("port", file.port, 11286);
+    let domain = sources.optional("domain", file.domain).unwrap_or_else(|| host.clone());
+    sources.check(webpki::DNSNameRef::try_from_ascii_str(&domain).is_ok(),
+                  format!("{} is not a valid domain, give one with --domain", domain));
+
+    let certs = sources.list("certificate", file.certificate, &[]);
+    for cert in certs.iter() {
+        sources.check(Path::new(cert).is_file(), format!("Certificate {} doesn't exist", cert));
+    }
+    let system_roots = sources.flag("system_roots", file.system_roots);
+    let mut pins = Vec::new();
+    for pin in sources.list("pin", file.pin, &[]) {
+        match pin.parse::<ssl::Pin>() {
+            Ok(pin) => pins.push(pin),
+            Err(e) => sources.problems.push(e)
+        }
+    }
+    let client_cert: Option<String> = sources.optional("client_cert", file.client_cert);
+    let client_key: Option<String> = sources.optional("client_key", file.client_key);
+    let client_auth = match (client_cert, client_key) {
+        (Some(cert), Some(key)) => {
+            sources.check(Path::new(&cert).is_file(), format!("Client certificate {} doesn't exist", cert));
+            sources.check(Path::new(&key).is_file(), format!("Client key {} doesn't exist", key));
+            Some((cert, key))
+        },
+        (None, None) => None,
+        _ => {
+            sources.problems.push("client-cert and client-key have to be given together".to_owned());
+            None
+        }
+    };
+
+    let name = sources.optional("name", file.name).unwrap_or_else(handshake::hostname);
+    let heartbeat = sources.value("heartbeat", file.heartbeat, 10);
+    let server_timeout = sources.value("server_timeout", file.server_timeout, 60);
+    sources.check(heartbeat > 0, "heartbeat has to be at least a second".to_owned());
+    sources.check(server_timeout > heartbeat,
+                  format!("server-timeout ({}s) has to be longer than heartbeat ({}s)", server_timeout, heartbeat));
+
+    if sources.problems.is_empty() {
+        Ok(Options {
+            host:           host,
+            port:           port,
+            domain:         domain,
+            trust:          ssl::Trust { certs: certs, system_roots: system_roots, pins: pins },
+            client_auth:    client_auth,
+            name:           name,
+            heartbeat:      Duration::from_secs(heartbeat),
+            server_timeout: Duration::from_secs(server_timeout),
+            settings:       settings
+        })
+    } else {
+        Err(JudgeError::Config(sources.problems))
+    }
+}
@@ -0,0 +1,68 @@
+extern crate rustls;
+use rustls::ClientSession;
+
+extern crate futures;
+use futures::Future;
+
+extern crate tokio_core;
+use tokio_core::{ net, reactor };
+
+extern crate tokio_rustls;
+use tokio_rustls::{ ClientConfigExt, TlsStream };
+
+extern crate webpki;
+
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type Client = TlsStream<net::TcpStream, ClientSession>;
+
+// Exponential backoff between connection attempts
+pub struct Backoff {
+    current:    Duration,
+    initial:    Duration,
+    max:        Duration
+}
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            current:    initial,
+            initial:    initial,
+            max:        max
+        }
+    }
+    pub fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+// Resolves `host` each time, so a lookup that fails is retried like any other failed connection
+pub fn connect(core: &mut reactor::Core, host: &str, port: u16, domain: &str, config: Arc<rustls::ClientConfig>) -> Result<Client, io::Error> {
+    let domain = webpki::DNSNameRef::try_from_ascii_str(domain)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a valid domain", domain)))?
+        .to_owned();
+    let addr = (host, port).to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", host)))?;
+    info!("Trying to connect to server!");
+    let connection = net::TcpStream::connect(&addr, &core.handle()).map_err(|e| {
+        error!("Error connecting to TCP server {}! {}", addr, e);
+        e
+    }).and_then(move |stream| {
+        info!("Made TCP connection to {}", addr);
+        info!("Trying to handshake SSL");
+        config.connect_async(domain.as_ref(), stream).map_err(|e| {
+            error!("Error connecting to SSL! {}", e);
+            e
+        })
+    });
+    let client = core.run(connection)?;
+    info!("SSL connected");
+    Ok(client)
+}
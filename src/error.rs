@@ -0,0 +1,75 @@
+use std::{ error, fmt, io };
+
+#[derive(Debug)]
+pub enum JudgeError {
+    Io(io::Error),
+    // The executor YAML at a path is missing or malformed
+    Executor(String, String),
+    // The sandbox profile at a path is missing or malformed
+    Profile(String, String),
+    Certificate(String),
+    // Everything wrong with the judge's configuration
+    Config(Vec<String>),
+    // The problem package at a path is missing or malformed
+    Problem(String, String),
+    // Couldn't fork or set up pipes for a submission
+    Spawn(io::Error),
+    // The other end of a channel between the judge and the server has gone away
+    Disconnected,
+    // The server broke the protocol
+    Protocol(String),
+    // The server refused to let us judge
+    Rejected(String)
+}
+
+impl fmt::Display for JudgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JudgeError::Io(ref e) => write!(f, "{}", e),
+            JudgeError::Executor(ref path, ref e) => write!(f, "Bad executor {}: {}", path, e),
+            JudgeError::Profile(ref path, ref e) => write!(f, "Bad sandbox profile {}: {}", path, e),
+            JudgeError::Certificate(ref e) => write!(f, "Bad certificate: {}", e),
+            JudgeError::Config(ref problems) => write!(f, "Bad configuration: {}", problems.join("; ")),
+            JudgeError::Problem(ref path, ref e) => write!(f, "Bad problem {}: {}", path, e),
+            JudgeError::Spawn(ref e) => write!(f, "Couldn't start process: {}", e),
+            JudgeError::Disconnected => write!(f, "Judge channel disconnected"),
+            JudgeError::Protocol(ref e) => write!(f, "Protocol error: {}", e),
+            JudgeError::Rejected(ref e) => write!(f, "Rejected by the server: {}", e)
+        }
+    }
+}
+
+impl error::Error for JudgeError {
+    fn description(&self) -> &str {
+        match *self {
+            JudgeError::Io(_) => "I/O error",
+            JudgeError::Executor(_, _) => "bad executor",
+            JudgeError::Profile(_, _) => "bad sandbox profile",
+            JudgeError::Certificate(_) => "bad certificate",
+            JudgeError::Config(_) => "bad configuration",
+            JudgeError::Problem(_, _) => "bad problem",
+            JudgeError::Spawn(_) => "couldn't start process",
+            JudgeError::Disconnected => "judge channel disconnected",
+            JudgeError::Protocol(_) => "protocol error",
+            JudgeError::Rejected(_) => "rejected by the server"
+        }
+    }
+}
+
+impl From<io::Error> for JudgeError {
+    fn from(e: io::Error) -> JudgeError {
+        JudgeError::Io(e)
+    }
+}
+
+impl From<JudgeError> for io::Error {
+    fn from(e: JudgeError) -> io::Error {
+        match e {
+            JudgeError::Io(e) => e,
+            JudgeError::Disconnected => io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()),
+            JudgeError::Protocol(_) => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            JudgeError::Rejected(_) => io::Error::new(io::ErrorKind::PermissionDenied, e.to_string()),
+            e => io::Error::new(io::ErrorKind::Other, e.to_string())
+        }
+    }
+}
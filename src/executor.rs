@@ -1,21 +1,107 @@
 extern crate serde_yaml;
 
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{ self, File };
+use std::path::Path;
+
+use super::error::JudgeError;
+use super::judge;
+use super::profile::{ self, Profile };
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Executor {
     pub filename:               String,
     pub pre_exec:               Option<String>,
     pub exec:                   String,
-    pub additional_syscalls:    Vec<u64>,
+    // A profile from the `profiles` directory beside the executor, which the rules below add to
+    pub profile:                Option<String>,
+    // What to do with syscalls beyond the standard ones, by name, e.g. `clone: allow` or
+    // `sched_getaffinity: { errno: 1 }`
+    #[serde(default)]
+    pub syscalls:               BTreeMap<String, judge::Action>,
+    // The same for 32-bit syscalls, for runtimes that still use `int 0x80`. Every 32-bit
+    // syscall is blocked when this is empty.
+    #[serde(default)]
+    pub compat_syscalls:        BTreeMap<String, judge::Action>,
+    // Where the runtime lives, if it needs to read more than the standard library paths
+    #[serde(default)]
+    pub read_paths:             Vec<String>,
+    // Syscall numbers to allow, from before `syscalls`. These are moved over to `syscalls` when loaded.
+    #[serde(default, skip_serializing)]
+    pub additional_syscalls:    Option<Vec<u64>>,
+    // The profile with this executor's own rules laid over it, once loaded
+    #[serde(skip)]
+    pub sandbox:                Profile,
+}
+
+// Every language with a usable executor in `dir`
+pub fn languages(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Couldn't read executors from {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut languages: Vec<String> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "yaml"))
+        .filter(|path| match Executor::from_file(path) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Skipping {}", e);
+                false
+            }
+        })
+        .filter_map(|path| path.file_stem().map(|lang| lang.to_string_lossy().into_owned()))
+        .collect();
+    languages.sort();
+    languages
 }
 
 impl Executor {
-    pub fn from_file(file: &mut File) -> Executor {
-        serde_yaml::from_reader(file).unwrap()
+    pub fn from_file(path: &Path) -> Result<Executor, JudgeError> {
+        let describe = |e: &dyn ToString| JudgeError::Executor(path.display().to_string(), e.to_string());
+        let file = File::open(path).map_err(|e| describe(&e))?;
+        let mut executor: Executor = serde_yaml::from_reader(file).map_err(|e| describe(&e))?;
+        if executor.exec.trim().is_empty() {
+            return Err(describe(&"exec can't be empty"));
+        }
+        if let Some(numbers) = executor.additional_syscalls.take() {
+            warn!("{}: additional_syscalls is deprecated, list syscalls by name under syscalls instead", path.display());
+            for nr in numbers {
+                let name = judge::Arch::X86_64.name(nr)
+                    .ok_or_else(|| describe(&format!("Unknown syscall {} in additional_syscalls", nr)))?;
+                executor.syscalls.entry(name.to_owned()).or_insert(judge::Action::Allow);
+            }
+        }
+        let base = match executor.profile {
+            Some(ref name) => {
+                let dir = path.parent().unwrap_or_else(|| Path::new(".")).join(profile::DIR);
+                Profile::load(&dir, name)?
+            },
+            None => Profile::default()
+        };
+        let own = Profile {
+            syscalls:           executor.syscalls.clone(),
+            compat_syscalls:    executor.compat_syscalls.clone(),
+            read_paths:         executor.read_paths.clone(),
+            ..Profile::default()
+        };
+        executor.sandbox = own.over(base);
+        executor.policy().map_err(|e| describe(&e))?;
+        Ok(executor)
+    }
+    // The standard syscalls along with the rules from the profile and this executor
+    pub fn policy(&self) -> Result<judge::Policy, String> {
+        let mut policy = judge::Policy::standard();
+        policy.add(&self.sandbox.syscalls, &self.sandbox.compat_syscalls)?;
+        policy.threads = self.sandbox.threads;
+        Ok(policy)
     }
     #[allow(dead_code)] // Remove this once we actually call to_file()
-    pub fn to_file(&self, file: &mut File) {
-        serde_yaml::to_writer(file, self).unwrap();
+    pub fn to_file(&self, file: &mut File) -> Result<(), JudgeError> {
+        serde_yaml::to_writer(file, self).map_err(|e| JudgeError::Executor("<output>".to_owned(), e.to_string()))
     }
 }
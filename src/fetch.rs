@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+
+use super::judge;
+
+// Submissions waiting on test data from the server. Anything still waiting when the connection
+// drops is forgotten along with it, since the server hands out unfinished submissions again.
+pub struct Fetches {
+    waiting:    Vec<(judge::ToMark, HashSet<String>)>,
+    requested:  HashSet<String>
+}
+impl Fetches {
+    pub fn new() -> Fetches {
+        Fetches {
+            waiting:    Vec::new(),
+            requested:  HashSet::new()
+        }
+    }
+    // Parks `mark` until everything in `missing` has arrived, returning the hashes that
+    // haven't already been asked for
+    pub fn wait(&mut self, mark: judge::ToMark, missing: HashSet<String>) -> Vec<String> {
+        let ask: Vec<String> = missing.iter().filter(|hash| !self.requested.contains(*hash)).cloned().collect();
+        self.requested.extend(ask.iter().cloned());
+        self.waiting.push((mark, missing));
+        ask
+    }
+    // Submissions that have all their test data now that `hash` is here
+    pub fn arrived(&mut self, hash: &str) -> Vec<judge::ToMark> {
+        self.requested.remove(hash);
+        let mut ready = Vec::new();
+        for (mark, mut missing) in self.waiting.drain(..).collect::<Vec<_>>() {
+            missing.remove(hash);
+            if missing.is_empty() {
+                ready.push(mark);
+            } else {
+                self.waiting.push((mark, missing));
+            }
+        }
+        ready
+    }
+    // Gives up on the submissions that need `hash`, returning their batches
+    pub fn failed(&mut self, hash: &str) -> Vec<u32> {
+        self.requested.remove(hash);
+        let (failed, waiting): (Vec<_>, Vec<_>) = self.waiting.drain(..).partition(|&(_, ref missing)| missing.contains(hash));
+        self.waiting = waiting;
+        failed.into_iter().map(|(mark, _)| mark.batch).collect()
+    }
+}
+impl Default for Fetches {
+    fn default() -> Fetches {
+        Fetches::new()
+    }
+}
@@ -0,0 +1,69 @@
+extern crate futures;
+use futures::{ Future, Sink, Stream };
+
+extern crate libc;
+
+extern crate pcs_protocol;
+use pcs_protocol::{ MsgHello, MsgType };
+
+extern crate tokio_core;
+use tokio_core::reactor;
+
+extern crate tokio_io;
+use tokio_io::{ AsyncRead, AsyncWrite, codec::{ FramedRead, FramedWrite } };
+
+use std::path::Path;
+
+use super::codec::{ Frame, MsgCodec };
+use super::error::JudgeError;
+use super::{ executor, judge };
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as _, buf.len()) } != 0 {
+        return "judge".to_owned();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// Memory each worker can hand to a submission without the judge machine swapping, in KiB
+fn memory_per_worker(workers: usize) -> Option<u64> {
+    let (pages, page_size) = unsafe { (libc::sysconf(libc::_SC_PHYS_PAGES), libc::sysconf(libc::_SC_PAGESIZE)) };
+    if pages <= 0 || page_size <= 0 {
+        return None;
+    }
+    Some(pages as u64 * page_size as u64 / 1024 / workers.max(1) as u64)
+}
+
+pub fn hello(name: &str, settings: &judge::Settings) -> MsgHello {
+    let languages = executor::languages(Path::new(&settings.executors));
+    info!("Judging {}", languages.join(", "));
+    MsgHello {
+        name:       name.to_owned(),
+        version:    env!("CARGO_PKG_VERSION").to_owned(),
+        languages:  languages,
+        workers:    settings.workers as u32,
+        max_memory: memory_per_worker(settings.workers),
+        // Filled in for each connection, since the store changes while we're running
+        have:       Vec::new()
+    }
+}
+
+// Introduce ourselves and wait for the server to accept us before anything else is sent
+pub fn handshake<R: AsyncRead, W: AsyncWrite>(core: &mut reactor::Core, read: FramedRead<R, MsgCodec>,
+                                              write: FramedWrite<W, MsgCodec>, hello: &MsgHello)
+                                              -> Result<(FramedRead<R, MsgCodec>, FramedWrite<W, MsgCodec>), JudgeError> {
+    let write = core.run(write.send(MsgType::Hello(hello.clone())))?;
+    let (reply, read) = core.run(read.into_future()).map_err(|(e, _)| e)?;
+    match reply {
+        Some(Frame::Msg(MsgType::Accepted)) => {
+            info!("Server accepted us as {}", hello.name);
+            Ok((read, write))
+        },
+        Some(Frame::Msg(MsgType::Rejected(reason))) => Err(JudgeError::Rejected(reason)),
+        Some(Frame::Msg(msg)) => Err(JudgeError::Protocol(format!("Expected a reply to our hello, got {:?}", msg))),
+        Some(Frame::Corrupt(e)) => Err(JudgeError::Protocol(e)),
+        None => Err(JudgeError::Protocol("Server hung up during the handshake".to_owned()))
+    }
+}
@@ -0,0 +1,44 @@
+extern crate futures;
+use futures::{ Future, Stream };
+
+extern crate pcs_protocol;
+use pcs_protocol::{ MsgHeartbeat, MsgType };
+
+extern crate tokio_core;
+use tokio_core::reactor;
+
+use std::io;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use super::codec::{ self, Outgoing };
+use super::judge;
+
+// How far past its limit a case can run before we call the worker stuck
+const STUCK_SLACK: u64 = 30;
+
+// Tells the server how loaded we are every `every`, and ends the connection if we haven't
+// heard from the server in `timeout` so that we reconnect
+pub fn heartbeat(handle: &reactor::Handle, every: Duration, timeout: Duration, status: Arc<judge::Status>,
+                 seen: Arc<Mutex<Instant>>, write: Outgoing) -> Result<Box<dyn Future<Item = (), Error = io::Error>>, io::Error> {
+    let interval = reactor::Interval::new(every, handle)?;
+    Ok(Box::new(interval.for_each(move |_| {
+        let silent = seen.lock().map(|seen| seen.elapsed()).unwrap_or_default();
+        if silent > timeout {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("Haven't heard from the server in {:?}", silent)));
+        }
+
+        let stuck = status.stuck(Duration::from_secs(STUCK_SLACK));
+        for running in stuck.iter() {
+            error!("Batch {} case {} has been running for {:?}, past its limit of {:?}",
+                   running.batch, running.case, running.since.elapsed(), running.limit);
+        }
+        let msg = MsgType::Heartbeat(MsgHeartbeat {
+            queued:     status.queued() as u32,
+            running:    status.running().iter().map(|r| r.batch).collect(),
+            stuck:      stuck.iter().map(|r| r.batch).collect(),
+            uptime:     status.uptime().as_secs()
+        });
+        codec::queue(&write, msg)
+    })))
+}
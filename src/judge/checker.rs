@@ -0,0 +1,392 @@
+extern crate libc;
+
+use std::{ env, fs, io::Write, path::PathBuf, str::FromStr };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Duration;
+
+use super::{ debugger, pipe, MarkResult, Policy, Sandbox };
+
+static SPECIAL_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+// Limits for a special judge, in seconds
+const SPECIAL_TIME: libc::rlim_t = 10;
+const SPECIAL_WALL: u64 = 20;
+
+pub enum Verdict {
+    Accept,
+    Reject(String),
+    // The checker itself failed, so we can't say anything about the answer
+    Fault(String)
+}
+
+pub trait Checker {
+    fn check(&self, input: &str, expected: &str, output: &str) -> Verdict;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    Exact,
+    Lines,
+    Tokens,
+    CaseInsensitive,
+    Float { abs: f64, rel: f64 },
+    Special(String)
+}
+impl Kind {
+    // `policy` is only used by a special judge
+    pub fn checker(&self, sandbox: Sandbox, policy: Policy) -> Box<dyn Checker> {
+        match *self {
+            Kind::Exact => Box::new(Exact),
+            Kind::Lines => Box::new(Lines),
+            Kind::Tokens => Box::new(Tokens { ignore_case: false }),
+            Kind::CaseInsensitive => Box::new(Tokens { ignore_case: true }),
+            Kind::Float { abs, rel } => Box::new(Float { abs: abs, rel: rel }),
+            Kind::Special(ref exe) => Box::new(Special { exe: exe.clone(), sandbox: sandbox, policy: policy })
+        }
+    }
+}
+impl Default for Kind {
+    fn default() -> Kind {
+        Kind::Exact
+    }
+}
+// Parses the checker names the server sends, i.e. "exact", "lines", "tokens",
+// "case-insensitive", "float[:eps[:rel_eps]]" and "special:<path>"
+impl FromStr for Kind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Kind, String> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let rest = parts.next();
+        match (name, rest) {
+            ("exact", None) | ("", None) => Ok(Kind::Exact),
+            ("lines", None) => Ok(Kind::Lines),
+            ("tokens", None) => Ok(Kind::Tokens),
+            ("case-insensitive", None) => Ok(Kind::CaseInsensitive),
+            ("float", eps) => {
+                let eps: Vec<&str> = eps.map(|e| e.split(':').collect()).unwrap_or_default();
+                let parse = |e: Option<&&str>| -> Result<f64, String> {
+                    match e {
+                        Some(e) => e.parse().map_err(|_| format!("Bad epsilon {} for float checker", e)),
+                        None => Ok(1e-9)
+                    }
+                };
+                let abs = parse(eps.get(0))?;
+                let rel = match eps.get(1) {
+                    Some(_) => parse(eps.get(1))?,
+                    None => abs
+                };
+                Ok(Kind::Float { abs: abs, rel: rel })
+            },
+            ("special", Some(exe)) if !exe.is_empty() => Ok(Kind::Special(exe.to_owned())),
+            _ => Err(format!("Unknown checker {}", s))
+        }
+    }
+}
+
+fn describe(expected: &str, found: &str) -> String {
+    format!("Expected {:?}, found {:?}", expected, found)
+}
+
+pub struct Exact;
+impl Checker for Exact {
+    fn check(&self, _input: &str, expected: &str, output: &str) -> Verdict {
+        if expected == output {
+            return Verdict::Accept;
+        }
+        // Only the first line that differs, rather than all of the output
+        let mut found = output.split('\n');
+        for (i, e) in expected.split('\n').enumerate() {
+            match found.next() {
+                Some(o) if o == e => {},
+                Some(o) => return Verdict::Reject(format!("Line {}: {}", i + 1, describe(e, o))),
+                None => return Verdict::Reject(format!("Output ended early at line {}", i + 1))
+            }
+        }
+        Verdict::Reject("Output has extra lines".to_owned())
+    }
+}
+
+// Ignores trailing whitespace on each line, `\r\n` line endings and trailing blank lines
+pub struct Lines;
+impl Lines {
+    fn lines(s: &str) -> Vec<&str> {
+        let mut lines: Vec<&str> = s.lines().map(|l| l.trim_end()).collect();
+        while lines.last() == Some(&"") {
+            lines.pop();
+        }
+        lines
+    }
+}
+impl Checker for Lines {
+    fn check(&self, _input: &str, expected: &str, output: &str) -> Verdict {
+        let expected = Lines::lines(expected);
+        let output = Lines::lines(output);
+        for (i, (e, o)) in expected.iter().zip(output.iter()).enumerate() {
+            if e != o {
+                return Verdict::Reject(format!("Line {}: {}", i + 1, describe(e, o)));
+            }
+        }
+        if expected.len() != output.len() {
+            return Verdict::Reject(format!("Expected {} lines, found {}", expected.len(), output.len()));
+        }
+        Verdict::Accept
+    }
+}
+
+// Compares whitespace separated tokens
+pub struct Tokens {
+    ignore_case: bool
+}
+impl Checker for Tokens {
+    fn check(&self, _input: &str, expected: &str, output: &str) -> Verdict {
+        let mut found = output.split_whitespace();
+        for (i, e) in expected.split_whitespace().enumerate() {
+            let o = match found.next() {
+                Some(o) => o,
+                None => return Verdict::Reject(format!("Output ended early at token {}", i + 1))
+            };
+            let same = if self.ignore_case {
+                e.to_lowercase() == o.to_lowercase()
+            } else {
+                e == o
+            };
+            if !same {
+                return Verdict::Reject(format!("Token {}: {}", i + 1, describe(e, o)));
+            }
+        }
+        if found.next().is_some() {
+            return Verdict::Reject("Output has extra tokens".to_owned());
+        }
+        Verdict::Accept
+    }
+}
+
+// Compares tokens, allowing numbers to be within an absolute or relative error
+pub struct Float {
+    abs: f64,
+    rel: f64
+}
+impl Float {
+    fn close(&self, e: f64, o: f64) -> bool {
+        if e.is_nan() || o.is_nan() {
+            return false;
+        }
+        let diff = (e - o).abs();
+        diff <= self.abs || diff <= self.rel * e.abs()
+    }
+}
+impl Checker for Float {
+    fn check(&self, _input: &str, expected: &str, output: &str) -> Verdict {
+        let mut found = output.split_whitespace();
+        for (i, e) in expected.split_whitespace().enumerate() {
+            let o = match found.next() {
+                Some(o) => o,
+                None => return Verdict::Reject(format!("Output ended early at token {}", i + 1))
+            };
+            let same = match (e.parse::<f64>(), o.parse::<f64>()) {
+                (Ok(e), Ok(o)) => self.close(e, o),
+                (Ok(_), Err(_)) => false,
+                _ => e == o
+            };
+            if !same {
+                return Verdict::Reject(format!("Token {}: {}", i + 1, describe(e, o)));
+            }
+        }
+        if found.next().is_some() {
+            return Verdict::Reject("Output has extra tokens".to_owned());
+        }
+        Verdict::Accept
+    }
+}
+
+// Removes the files handed to a special judge or interactor once it has finished
+pub struct Scratch {
+    files: Vec<PathBuf>
+}
+impl Scratch {
+    pub fn new() -> Scratch {
+        Scratch { files: Vec::new() }
+    }
+    pub fn path(&mut self, name: &str) -> PathBuf {
+        let id = SPECIAL_RUNS.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("pcs_judge_{}_{}_{}", unsafe { libc::getpid() }, id, name));
+        self.files.push(path.clone());
+        path
+    }
+    pub fn write(&mut self, name: &str, contents: &str) -> Result<String, String> {
+        let path = self.path(name);
+        let mut file = fs::File::create(&path).map_err(|e| format!("Couldn't create {:?}: {}", path, e))?;
+        file.write_all(contents.as_bytes()).map_err(|e| format!("Couldn't write {:?}: {}", path, e))?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+}
+impl Default for Scratch {
+    fn default() -> Scratch {
+        Scratch::new()
+    }
+}
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        for file in self.files.iter() {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+// Runs a problem-supplied checker as `<exe> <input> <expected> <output>` under the sandbox.
+// Exiting successfully accepts the answer, and anything written to stdout is the message.
+pub struct Special {
+    exe:        String,
+    sandbox:    Sandbox,
+    policy:     Policy
+}
+impl Checker for Special {
+    fn check(&self, input: &str, expected: &str, output: &str) -> Verdict {
+        let mut scratch = Scratch::new();
+        let files = scratch.write("input", input)
+            .and_then(|i| scratch.write("expected", expected).map(|e| (i, e)))
+            .and_then(|(i, e)| scratch.write("output", output).map(|o| (i, e, o)));
+        let (i, e, o) = match files {
+            Ok(files) => files,
+            Err(e) => return Verdict::Fault(e)
+        };
+
+        let process = debugger::Process::new(self.exe.as_str(), &[i.as_str(), e.as_str(), o.as_str()],
+                                             Some(SPECIAL_TIME), None);
+        let mut process = match process {
+            Ok(process) => process,
+            Err(e) => return Verdict::Fault(format!("Couldn't start special judge {}: {}", self.exe, e))
+        };
+        process.m_wall = Some(Duration::from_secs(SPECIAL_WALL));
+        process.sandbox(self.sandbox, &self.policy);
+        if let Err(e) = process.run() {
+            return Verdict::Fault(format!("Couldn't start special judge {}: {}", self.exe, e));
+        }
+        unsafe { libc::close(process.stdin) };
+        let drainer = pipe::drain(process.stdout, pipe::OUTPUT_LIMIT);
+        { // Give debugger an explicit lifetime
+            let mut debugger = debugger::Debugger::new(&mut process);
+            debugger.policy(&self.policy);
+            debugger.monitor();
+        }
+        let message = String::from_utf8_lossy(&drainer.join().unwrap_or_default()).into_owned();
+        match process.reason {
+            MarkResult::Success(_, _) => Verdict::Accept,
+            MarkResult::RTE => Verdict::Reject(message),
+            x => Verdict::Fault(format!("Special judge {} failed: {:?}", self.exe, x))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(checker: &dyn Checker, expected: &str, output: &str) -> Option<String> {
+        match checker.check("", expected, output) {
+            Verdict::Accept => None,
+            Verdict::Reject(message) | Verdict::Fault(message) => Some(message)
+        }
+    }
+
+    // Each case is (expected, output, whether it's accepted)
+    fn table(checker: &dyn Checker, cases: &[(&str, &str, bool)]) {
+        for &(expected, output, accepted) in cases {
+            let verdict = verdict(checker, expected, output);
+            assert!(verdict.is_none() == accepted, "{:?} against {:?} gave {:?}", output, expected, verdict);
+        }
+    }
+
+    #[test]
+    fn parses_checkers() {
+        let cases = [
+            ("", Kind::Exact),
+            ("exact", Kind::Exact),
+            ("lines", Kind::Lines),
+            ("tokens", Kind::Tokens),
+            ("case-insensitive", Kind::CaseInsensitive),
+            ("float", Kind::Float { abs: 1e-9, rel: 1e-9 }),
+            ("float:1e-6", Kind::Float { abs: 1e-6, rel: 1e-6 }),
+            ("float:1e-6:1e-4", Kind::Float { abs: 1e-6, rel: 1e-4 }),
+            ("special:/bin/check", Kind::Special("/bin/check".to_owned()))
+        ];
+        for &(spec, ref kind) in cases.iter() {
+            assert_eq!(spec.parse::<Kind>().as_ref(), Ok(kind), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_checkers() {
+        for spec in &["bogus", "lines:1", "exact:", "float:abc", "float:1e-6:x", "special", "special:"] {
+            assert!(spec.parse::<Kind>().is_err(), "{} parsed", spec);
+        }
+    }
+
+    #[test]
+    fn exact() {
+        table(&Exact, &[
+            ("1 2\n", "1 2\n", true),
+            ("1 2\n", "1 2", false),
+            ("1 2\n", "1 2\r\n", false),
+            ("1 2\n", "1  2\n", false)
+        ]);
+        assert_eq!(verdict(&Exact, "a\nb\nc\n", "a\nx\nc\n").unwrap(), "Line 2: Expected \"b\", found \"x\"");
+        assert_eq!(verdict(&Exact, "a\nb", "a").unwrap(), "Output ended early at line 2");
+        assert_eq!(verdict(&Exact, "a", "a\nb").unwrap(), "Output has extra lines");
+    }
+
+    #[test]
+    fn lines() {
+        table(&Lines, &[
+            ("1\n2\n", "1\n2\n", true),
+            ("1\n2\n", "1\r\n2\r\n", true),
+            ("1\n2\n", "1\n2", true),
+            ("1\n2\n", "1\n2\n\n\n", true),
+            ("1\n2\n\n", "1\n2\n", true),
+            ("1\n2\n", "1  \n2\t\n", true),
+            ("1\n2\n", " 1\n2\n", false),
+            ("1\n2\n", "1 2\n", false),
+            ("1\n\n2\n", "1\n2\n", false),
+            ("1\n2\n", "1\n2\n3\n", false)
+        ]);
+    }
+
+    #[test]
+    fn tokens() {
+        table(&Tokens { ignore_case: false }, &[
+            ("1 2\n", "1\n2", true),
+            ("1 2\n", "  1 \r\n 2  \n\n", true),
+            ("yes\n", "YES\n", false),
+            ("1 2\n", "1\n", false),
+            ("1 2\n", "1 2 3\n", false)
+        ]);
+        table(&Tokens { ignore_case: true }, &[
+            ("yes\n", "YES\n", true),
+            ("Yes No\n", "yES nO\n", true),
+            ("yes\n", "no\n", false)
+        ]);
+    }
+
+    #[test]
+    fn float() {
+        table(&Float { abs: 0.5, rel: 0.0 }, &[
+            ("1.0", "1.5", true),
+            ("1.0", "0.5", true),
+            ("1.0", "1.5000001", false),
+            ("nan", "nan", false),
+            ("1.0", "nan", false),
+            ("1.0", "one", false),
+            ("one", "one", true),
+            ("1.0 2.0", "1.0", false),
+            ("1.0", "1.0 2.0", false)
+        ]);
+        table(&Float { abs: 0.0, rel: 0.5 }, &[
+            ("2.0", "3.0", true),
+            ("2.0", "1.0", true),
+            ("2.0", "3.5", false),
+            ("-2.0", "-3.0", true),
+            ("0", "0.0001", false)
+        ]);
+    }
+}
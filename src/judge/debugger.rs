@@ -2,8 +2,308 @@ use super::MarkResult;
 
 extern crate libc;
 
-use std::collections::BTreeSet;
+use super::JudgeError;
+use super::isolate::{ self, Jail };
+use super::seccomp;
+use super::syscall::{ self, Arch };
+
+use std::collections::BTreeMap;
 use std::ffi::*;
+use std::{ env, io, ptr };
+use std::os::unix::ffi::OsStrExt;
+use std::path::{ Component, Path, PathBuf };
+use std::str::FromStr;
+use std::sync::{ Arc, mpsc, atomic::{ AtomicBool, Ordering } };
+use std::{ thread, time::{ self, Duration } };
+
+pub const STANDARD: &[&str] = &[
+    "read",
+    "write",
+    "close",
+    "fstat",
+    "mmap",
+    "mprotect",
+    "munmap",
+    "brk",
+    "access",
+    "arch_prctl",
+    "exit_group",
+    "openat",
+];
+
+// Added to the standard syscalls for special judges and interactors, which are usually C or C++
+// built against glibc and make a few more syscalls starting up and doing I/O
+const HELPER: &[&str] = &[
+    "set_tid_address",
+    "set_robust_list",
+    "rseq",
+    "prlimit64",
+    "getrandom",
+    "newfstatat",
+    "pread64",
+    "lseek",
+    "ioctl",
+    "fcntl",
+    "futex",
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "clock_gettime",
+    "uname",
+    "mremap",
+    "madvise",
+    "exit",
+];
+
+// What every process may read, which is enough for a dynamically linked program to start
+pub const STANDARD_READ: &[&str] = &[
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/etc/ld.so.cache",
+    "/dev/null",
+    "/dev/urandom",
+];
+
+// Reported as the syscall when the seccomp filter killed the process itself, as the kernel
+// doesn't say which syscall it was
+pub const UNKNOWN_SYSCALL: u64 = u64::max_value();
+
+// The verdict for a process killed outright by the seccomp filter
+fn filtered() -> MarkResult {
+    MarkResult::Blocked(UNKNOWN_SYSCALL, Some("killed by the seccomp filter".to_owned()))
+}
+
+// Syscalls that fail with ENOMEM once the memory limit is hit. brk isn't here as it fails by
+// returning the old break, and glibc then falls back to mmap anyway.
+const ALLOCATES: &[&str] = &["mmap", "mmap2", "mremap"];
+
+fn allocates(arch: Arch, nr: u64) -> bool {
+    arch.name(nr).map_or(false, |name| ALLOCATES.contains(&name))
+}
+
+// Syscalls that look files up by path, which are checked against `Paths`
+const PATHED: &[&str] = &[
+    "open", "openat", "openat2", "creat",
+    "access", "faccessat", "faccessat2",
+    "stat", "lstat", "stat64", "lstat64", "newfstatat", "fstatat64", "statx",
+    "readlink", "readlinkat",
+];
+
+// Syscalls that start another task, which could change a path between our check and the kernel
+// reading it, as a thread or through memory it shares with its parent
+const TASKS: &[&str] = &["clone", "clone3", "fork", "vfork"];
+
+// Syscalls that make links, which can lead a path somewhere other than where it reads as going
+const LINKS: &[&str] = &["link", "linkat", "symlink", "symlinkat"];
+
+// What happens when a process makes a syscall. In executor YAML this is one of `allow`,
+// `kill`, `log` or `errno: <number>`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    // Fail with this errno without running the syscall, for runtimes that cope without it
+    Errno(u16),
+    // Kill the process with a Blocked verdict, like any syscall that isn't listed
+    Kill,
+    // Allow it, but say so in the judge's log, for finding out what a runtime needs
+    Log
+}
+
+// What to do with each syscall, by number, for each ABI a process can make them through.
+// Anything that isn't listed gets `unlisted`, which kills it unless said otherwise.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    pub native: BTreeMap<u64, Action>,
+    // 32-bit syscalls made through `int 0x80`
+    pub compat: BTreeMap<u64, Action>,
+    pub unlisted: Action,
+    // Where files may be opened, or anywhere if this isn't given
+    pub paths:  Option<Paths>,
+    // How many tasks may be running at once
+    pub threads: Option<usize>
+}
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            native:     BTreeMap::new(),
+            compat:     BTreeMap::new(),
+            unlisted:   Action::Kill,
+            paths:      None,
+            threads:    None
+        }
+    }
+}
+impl Policy {
+    pub fn standard() -> Policy {
+        let mut policy = Policy::default();
+        for name in STANDARD {
+            policy.native.insert(Arch::X86_64.number(name).unwrap(), Action::Allow);
+        }
+        policy
+    }
+    // For special judges and interactors, when the problem doesn't name a profile for them
+    pub fn helper() -> Policy {
+        let mut policy = Policy::standard();
+        for name in HELPER {
+            policy.native.insert(Arch::X86_64.number(name).unwrap(), Action::Allow);
+        }
+        policy
+    }
+    // Compilers run whatever else the toolchain needs, so there's no list of syscalls to hold them
+    // to. They're kept in check by the jail and their limits instead.
+    pub fn compiler() -> Policy {
+        Policy { unlisted: Action::Allow, ..Policy::default() }
+    }
+    fn resolve(arch: Arch, rules: &BTreeMap<String, Action>, to: &mut BTreeMap<u64, Action>) -> Result<(), String> {
+        for (name, action) in rules.iter() {
+            match arch.number(name) {
+                Some(nr) => { to.insert(nr, *action); },
+                None => return Err(format!("Unknown {:?} syscall {}", arch, name))
+            }
+        }
+        Ok(())
+    }
+    // Adds rules by syscall name, which replace any already given for the same syscall
+    pub fn add(&mut self, native: &BTreeMap<String, Action>, compat: &BTreeMap<String, Action>) -> Result<(), String> {
+        Self::resolve(Arch::X86_64, native, &mut self.native)?;
+        Self::resolve(Arch::I386, compat, &mut self.compat)
+    }
+    // Restricts where files may be opened, which can only be checked for a single task. Making
+    // links is blocked from then on, as paths are only checked by how they read.
+    pub fn restrict(&mut self, paths: Paths) -> Result<(), String> {
+        for &arch in &[Arch::X86_64, Arch::I386] {
+            let rules = if arch == Arch::X86_64 { &self.native } else { &self.compat };
+            for nr in TASKS.iter().filter_map(|name| arch.number(name)) {
+                let action = rules.get(&nr).cloned().unwrap_or(self.unlisted);
+                if action == Action::Allow || action == Action::Log {
+                    return Err(format!("Paths can't be checked when {} is allowed", arch.describe(nr)));
+                }
+            }
+        }
+        for &arch in &[Arch::X86_64, Arch::I386] {
+            let rules = if arch == Arch::X86_64 { &mut self.native } else { &mut self.compat };
+            for nr in LINKS.iter().filter_map(|name| arch.number(name)) {
+                rules.insert(nr, Action::Kill);
+            }
+        }
+        self.paths = Some(paths);
+        Ok(())
+    }
+    // Whether the tracer has to look at the arguments before the syscall can go ahead
+    fn inspects(&self, arch: Arch, syscall: u64) -> bool {
+        self.paths.is_some() && arch.name(syscall).map_or(false, |name| PATHED.contains(&name))
+    }
+}
+
+// Removes `.` and `..` without touching the filesystem, since the path is as the process sees it
+fn normalise(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { normal.pop(); },
+            c => normal.push(c.as_os_str())
+        }
+    }
+    normal
+}
+
+// Where a process may open files, as the process sees them. Each path covers everything under it.
+// A process with more than one task could change a path after it's been checked, so
+// `Policy::restrict` only takes these when nothing that starts one is allowed.
+#[derive(Clone, Debug)]
+pub struct Paths {
+    // Only opened for reading
+    pub read:   Vec<PathBuf>,
+    // Opened for anything, including creating files
+    pub write:  Vec<PathBuf>,
+    // What relative paths are relative to
+    pub cwd:    PathBuf
+}
+impl Paths {
+    // The standard paths and `read` for reading, and `work` for everything
+    pub fn new(work: PathBuf, read: &[String]) -> Paths {
+        Paths {
+            read:   STANDARD_READ.iter().map(PathBuf::from).chain(read.iter().map(PathBuf::from)).collect(),
+            write:  vec![work.clone()],
+            cwd:    work
+        }
+    }
+    // The path as it's reported if it can't be opened
+    fn check(&self, dirfd: i32, path: &Path, flags: i32) -> Result<(), String> {
+        // With AT_EMPTY_PATH this is the descriptor itself, which was checked when it was opened
+        if path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let path = if path.is_absolute() {
+            normalise(path)
+        } else if dirfd == libc::AT_FDCWD {
+            normalise(&self.cwd.join(path))
+        } else {
+            // We can't tell where the descriptor points as the process sees it
+            return Err(format!("{} relative to descriptor {}", path.display(), dirfd));
+        };
+        let writing = flags & libc::O_ACCMODE != libc::O_RDONLY
+            || flags & (libc::O_CREAT | libc::O_TRUNC | libc::O_TMPFILE) != 0;
+        let under = |prefixes: &[PathBuf]| prefixes.iter().any(|prefix| path.starts_with(prefix));
+        if under(&self.write) || (!writing && under(&self.read)) {
+            Ok(())
+        } else {
+            Err(path.display().to_string())
+        }
+    }
+}
+
+// The directory descriptor, path and open flags of a syscall that looks a file up by path, or
+// None for any other syscall. Those that only look at the file are counted as reading it.
+fn opened(tid: libc::pid_t, arch: Arch, syscall: u64, args: &[u64; 6]) -> Option<Result<(i32, u64, i32), String>> {
+    let reading = libc::O_RDONLY;
+    Some(Ok(match arch.name(syscall)? {
+        "openat" => (args[0] as i32, args[1], args[2] as i32),
+        "open" => (libc::AT_FDCWD, args[0], args[1] as i32),
+        "creat" => (libc::AT_FDCWD, args[0], libc::O_CREAT | libc::O_WRONLY | libc::O_TRUNC),
+        // The flags are the first field of struct open_how
+        "openat2" => match syscall::read_u64(tid, args[2]) {
+            Some(flags) => (args[0] as i32, args[1], flags as i32),
+            None => return Some(Err(format!("unreadable open_how at {:#x}", args[2])))
+        },
+        "access" | "stat" | "lstat" | "stat64" | "lstat64" | "readlink" => (libc::AT_FDCWD, args[0], reading),
+        "faccessat" | "faccessat2" | "newfstatat" | "fstatat64" | "statx" | "readlinkat" => (args[0] as i32, args[1], reading),
+        _ => return None
+    }))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sandbox {
+    // Stop on every syscall entry and exit
+    Ptrace,
+    // Seccomp filter that only wakes the tracer for disallowed syscalls
+    SeccompTrace,
+    // Seccomp filter that has the kernel kill the process on disallowed syscalls
+    SeccompKill
+}
+impl FromStr for Sandbox {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Sandbox, String> {
+        match s {
+            "ptrace"        => Ok(Sandbox::Ptrace),
+            "seccomp"       => Ok(Sandbox::SeccompTrace),
+            "seccomp-kill"  => Ok(Sandbox::SeccompKill),
+            x               => Err(format!("Unknown sandbox {}", x))
+        }
+    }
+}
+
+// Programs and their arguments can come from the server, so a NUL in one fails the batch
+// rather than panicking
+fn cstring<S: Into<Vec<u8>>>(s: S) -> Result<CString, JudgeError> {
+    CString::new(s).map_err(|e| {
+        let s = String::from_utf8_lossy(&e.into_vec()).into_owned();
+        JudgeError::Spawn(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} contains a NUL", s)))
+    })
+}
 
 pub struct Process {
     exe:        CString,
@@ -12,33 +312,126 @@ pub struct Process {
     pub stdin:  i32,
     pub stdout: i32,
     pub reason: MarkResult,
-    pub m_time: Option<libc::rlim_t>
+    // What the process exited with, if it exited rather than being killed
+    pub exit_code: Option<i32>,
+    pub m_time: Option<libc::rlim_t>,
+    pub m_mem:  Option<libc::rlim_t>,
+    pub m_wall: Option<Duration>,
+    // Whether `m_mem` is enforced with RLIMIT_AS, or with RLIMIT_DATA, which leaves out memory
+    // that's only reserved
+    limit_as:   bool,
+    io:         Option<(i32, i32)>,
+    cwd:        Option<CString>,
+    jail:       Option<Jail>,
+    // Whether stderr goes to the same pipe as stdout, rather than the judge's own stderr
+    merge_stderr: bool,
+    pub peak_mem: u64,
+    pub cpu_time: Duration,
+    pub wall_time: Duration,
+    sandbox:    Sandbox,
+    filter:     Option<seccomp::Filter>
 }
 impl Process {
-    pub fn new<S: Into<Vec<u8>> + Clone>(file: S, args: &[S], max_time: Option<libc::rlim_t>) -> Process {
-        use std::ptr;
-        let mut v = Vec::new();
+    // `max_memory` and `peak_mem` are in KiB, to match `ru_maxrss`
+    pub fn new<S: Into<Vec<u8>> + Clone>(file: S, args: &[S], max_time: Option<libc::rlim_t>,
+                                         max_memory: Option<libc::rlim_t>) -> Result<Process, JudgeError> {
+        // argv[0] is the program itself
+        let mut v = vec![cstring(file.clone())?];
         for i in args {
-            let a = i.clone();
-            v.push(CString::new(a).unwrap());
+            v.push(cstring(i.clone())?);
         }
-        v.push(unsafe { CString::from_raw(ptr::null_mut()) });
-        Process {
-            exe:    CString::new(file).unwrap(),
+        Ok(Process {
+            exe:    cstring(file)?,
             args:   v,
             pid:    None,
             stdin:  0,
             stdout: 0,
             reason: MarkResult::RTE,
-            m_time: max_time
+            exit_code: None,
+            m_time: max_time,
+            m_mem:  max_memory,
+            m_wall: None,
+            limit_as: true,
+            io:     None,
+            cwd:    None,
+            jail:   None,
+            merge_stderr: false,
+            peak_mem: 0,
+            cpu_time: Duration::default(),
+            wall_time: Duration::default(),
+            sandbox: Sandbox::Ptrace,
+            filter: None
+        })
+    }
+    // Set up the sandbox before `run()`, as the filter has to be built before we fork
+    pub fn sandbox(&mut self, sandbox: Sandbox, policy: &Policy) {
+        self.sandbox = sandbox;
+        let kill = match sandbox {
+            Sandbox::Ptrace => {
+                self.filter = None;
+                return;
+            },
+            Sandbox::SeccompTrace => seccomp::SECCOMP_RET_TRACE,
+            Sandbox::SeccompKill => seccomp::SECCOMP_RET_KILL_PROCESS
+        };
+        let ret = |action: &Action, traced: bool, kill: u32| match *action {
+            Action::Allow if traced => seccomp::SECCOMP_RET_TRACE,
+            Action::Allow => seccomp::SECCOMP_RET_ALLOW,
+            Action::Errno(errno) => seccomp::SECCOMP_RET_ERRNO | errno as u32,
+            // Only the tracer can write to the log
+            Action::Log => seccomp::SECCOMP_RET_TRACE,
+            Action::Kill => kill
+        };
+        // Only the tracer can look at the arguments, and only it sees an allocation fail, which
+        // is how we know the memory limit was hit
+        let memory = self.m_mem.is_some();
+        let traced = |arch: Arch, nr: u64| policy.inspects(arch, nr) || (memory && allocates(arch, nr));
+        let default = ret(&policy.unlisted, false, kill);
+        let mut native = Vec::new();
+        if sandbox == Sandbox::SeccompKill {
+            // The tracer lets the first execve through and blocks any after it
+            native.push((libc::SYS_execve as u64, seccomp::SECCOMP_RET_TRACE));
+        }
+        native.extend(policy.native.iter().map(|(&nr, action)| (nr, ret(action, traced(Arch::X86_64, nr), kill))));
+        // Blocked 32-bit syscalls always go to the tracer, so they're reported as Blocked
+        let compat: Vec<(u64, u32)> = policy.compat.iter()
+            .map(|(&nr, action)| (nr, ret(action, traced(Arch::I386, nr), seccomp::SECCOMP_RET_TRACE)))
+            .collect();
+        self.filter = Some(seccomp::Filter::new(&native, &compat, default));
+    }
+    pub fn address_space(&mut self, limit: bool) {
+        self.limit_as = limit;
+    }
+    pub fn cwd(&mut self, dir: &Path) {
+        self.cwd = Some(CString::new(dir.as_os_str().as_bytes()).unwrap());
+    }
+    // Run in new namespaces inside the jail's root instead of the judge's filesystem
+    pub fn jail(&mut self, jail: Jail) {
+        self.jail = Some(jail);
+    }
+    // Send stderr down the stdout pipe, for compilers whose errors are what we want back
+    pub fn merge_stderr(&mut self) {
+        self.merge_stderr = true;
+    }
+    // Use the given descriptors as stdin and stdout instead of making new pipes to the judge.
+    // They belong to the process from here on, and are closed in the judge once it starts.
+    pub fn stdio(&mut self, stdin: i32, stdout: i32) {
+        self.io = Some((stdin, stdout));
+    }
+    // Every pipe is close on exec, so only what's moved onto stdin and stdout survives execv
+    unsafe fn dup_onto(fd: i32, target: i32) {
+        if fd == target {
+            libc::fcntl(fd, libc::F_SETFD, 0);
+        } else {
+            libc::dup2(fd, target);
         }
     }
     fn prepare_child(ptc: [i32;2], ctp: [i32;2]) {
         unsafe {
             libc::close(ptc[1]);
-            libc::dup2(ptc[0], 0);
+            Self::dup_onto(ptc[0], 0);
             libc::close(ctp[0]);
-            libc::dup2(ptc[1], 1);
+            Self::dup_onto(ctp[1], 1);
         }
     }
     fn prepare_parent(ctp: [i32;2], ptc: [i32;2]) {
@@ -47,24 +440,66 @@ impl Process {
             libc::close(ptc[0]);
         }
     }
-    pub fn run(&mut self) {
-        use std::process;
+    pub fn run(&mut self) -> Result<(), JudgeError> {
         let mut ptc = [0i32;2];
         let mut ctp = [0i32;2];
-        unsafe { 
-            libc::pipe(&mut ptc as *mut [i32] as _);
-            libc::pipe(&mut ctp as *mut [i32] as _);
+        if let Some((stdin, stdout)) = self.io {
+            ptc = [stdin, -1];
+            ctp = [-1, stdout];
+        } else {
+            // Close on exec, so submissions forked by other workers don't inherit our ends
+            unsafe {
+                if libc::pipe2(&mut ptc as *mut [i32] as _, libc::O_CLOEXEC) != 0 {
+                    return Err(JudgeError::Spawn(io::Error::last_os_error()));
+                }
+                if libc::pipe2(&mut ctp as *mut [i32] as _, libc::O_CLOEXEC) != 0 {
+                    let e = io::Error::last_os_error();
+                    libc::close(ptc[0]);
+                    libc::close(ptc[1]);
+                    return Err(JudgeError::Spawn(e));
+                }
+            }
         }
-        let pid: libc::pid_t = unsafe { libc::fork() };
+        // The child can't allocate, so everything it execs with is laid out here. A jailed child is
+        // made with the raw clone syscall, which skips glibc's fork handlers, so another worker may
+        // have been holding the malloc lock.
+        let mut argv: Vec<*const libc::c_char> = self.args.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(ptr::null());
+        let vars: Vec<CString> = env::vars_os()
+            .filter_map(|(key, value)| {
+                let mut var = key.as_bytes().to_vec();
+                var.push(b'=');
+                var.extend_from_slice(value.as_bytes());
+                CString::new(var).ok()
+            })
+            .collect();
+        let mut envp: Vec<*const libc::c_char> = vars.iter().map(|var| var.as_ptr()).collect();
+        envp.push(ptr::null());
+        // Like fork, but a jailed child starts out as PID 1 of its own namespaces. It still stops
+        // itself once traced, as init only ignores signals when it isn't.
+        let pid: libc::pid_t = unsafe {
+            match self.jail {
+                Some(_) => libc::syscall(libc::SYS_clone, (isolate::NAMESPACES | libc::SIGCHLD) as libc::c_ulong,
+                                         0, 0, 0, 0) as libc::pid_t,
+                None => libc::fork()
+            }
+        };
         if pid == 0 {
-            use std::ptr;
             Self::prepare_child(ptc, ctp);
+            if self.merge_stderr {
+                unsafe { libc::dup2(1, 2) };
+            }
 
             unsafe { libc::setpgid(0, 0) };
 
-            let mut v = Vec::new();
-            for s in self.args.clone() {
-                v.push(s.as_ptr());
+            if let Some(ref jail) = self.jail {
+                if !unsafe { jail.enter() } {
+                    unsafe { libc::_exit(1) };
+                }
+            } else if let Some(ref cwd) = self.cwd {
+                if unsafe { libc::chdir(cwd.as_ptr()) } != 0 {
+                    unsafe { libc::_exit(1) };
+                }
             }
 
             if let Some(time) = self.m_time {
@@ -77,151 +512,558 @@ impl Process {
                 }
             }
 
+            if let Some(memory) = self.m_mem {
+                unsafe {
+                    use std::mem;
+                    let mut pass: libc::rlimit = mem::zeroed();
+                    pass.rlim_cur = memory * 1024;
+                    pass.rlim_max = memory * 1024;
+                    libc::setrlimit(if self.limit_as { libc::RLIMIT_AS } else { libc::RLIMIT_DATA }, &pass);
+                }
+            }
+
             unsafe {
                 libc::ptrace(libc::PTRACE_TRACEME, 0, ptr::null_mut::<libc::c_void>(), ptr::null_mut::<libc::c_void>());
                 libc::kill(libc::getpid(), libc::SIGSTOP);
-                libc::execv(self.exe.as_ptr(), &v[0]);
-                process::exit(1);
+                if let Some(ref filter) = self.filter {
+                    if !filter.install() {
+                        libc::_exit(1);
+                    }
+                }
+                libc::execve(self.exe.as_ptr(), argv.as_ptr(), envp.as_ptr());
+                libc::_exit(1);
             }
         } else if pid == -1 {
-            process::exit(1); // Temporary
+            let e = io::Error::last_os_error();
+            unsafe {
+                for fd in ptc.iter().chain(ctp.iter()) {
+                    libc::close(*fd);
+                }
+            }
+            Err(JudgeError::Spawn(e))
         } else {
             Self::prepare_parent(ctp, ptc);
             self.pid = Some(pid);
             self.stdin = ptc[1];
             self.stdout = ctp[0];
+            Ok(())
         }
     }
 }
 
+// What we know about each traced task, which is any thread of any process the submission starts
+#[derive(Default)]
+struct Task {
+    // Whether the task has had the stop every new tracee starts with
+    started:    bool,
+    // The syscall the task is in, between its entry stop and its exit stop
+    current:    Option<(Arch, u64)>,
+    // The errno that syscall has to fail with once it exits, when it's been skipped
+    failing:    Option<u16>
+}
+
 pub struct Debugger<'a> {
     process:    &'a mut Process,
-    handlers:   BTreeSet<u64>,
-    children:   BTreeSet<i32>
+    policy:     Policy,
+    tasks:      BTreeMap<libc::pid_t, Task>,
+    oom:        bool,
+    // Killed for starting more tasks than the policy allows
+    crowded:    bool
 }
 impl<'a> Debugger<'a> {
     pub fn new(process: &'a mut Process) -> Debugger {
         Debugger {
             process:    process,
-            handlers:   BTreeSet::new(),
-            children:   BTreeSet::new()
-        }
-    }
-    pub fn add_handler(&mut self, handlers: &[u64]) {
-        for syscall in handlers {
-            self.handlers.insert(*syscall);
-        }
-    }
-    pub fn standard(process: &'a mut Process) -> Debugger {
-        let mut d = Debugger::new(process);
-        d.add_handler(&[
-                      0,   // read
-                      1,   // write
-                      3,   // close
-                      5,   // fstat
-                      9,   // mmap
-                      10,  // mmap
-                      11,  // munmap
-                      12,  // brk
-                      21,  // access
-                      158, // arch_prctl
-                      231, // exit_group
-                      257, // openat
-        ]);
-        d
-    }
-    fn kill_children(&mut self) {
-        let mut v = Vec::new();
-        for child in self.children.iter() {
-            unsafe { libc::kill(*child, libc::SIGKILL) };
-            v.push(*child);
-        }
-        for child in v {
-            self.children.remove(&child);
-        }
-        return;
+            policy:     Policy::default(),
+            tasks:      BTreeMap::new(),
+            oom:        false,
+            crowded:    false
+        }
+    }
+    pub fn policy(&mut self, policy: &Policy) {
+        self.policy.native.extend(policy.native.iter());
+        self.policy.compat.extend(policy.compat.iter());
+        self.policy.unlisted = policy.unlisted;
+        if policy.paths.is_some() {
+            self.policy.paths = policy.paths.clone();
+        }
+        if policy.threads.is_some() {
+            self.policy.threads = policy.threads;
+        }
+    }
+    // Kills every task we know of, as well as anything left in the process group
+    fn kill_all(&mut self, p_pid: libc::pid_t) {
+        unsafe { libc::kill(-p_pid, libc::SIGKILL) };
+        for (&tid, _) in self.tasks.iter() {
+            unsafe { libc::kill(tid, libc::SIGKILL) };
+        }
+        self.tasks.clear();
+    }
+    // Waits out everything that was killed, so no traced task is left behind as a zombie
+    fn reap(p_pid: libc::pid_t) {
+        loop {
+            let pid = unsafe { libc::wait4(-p_pid, ptr::null_mut(), libc::__WALL, ptr::null_mut()) };
+            if pid < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return;
+            }
+        }
+    }
+    fn block(&mut self, p_pid: libc::pid_t, tid: libc::pid_t, arch: Arch, syscall: u64, path: Option<String>) {
+        match path {
+            Some(ref path) => warn!("Task {} of process {} made disallowed syscall {} on {}", tid, p_pid, arch.describe(syscall), path),
+            None => warn!("Task {} of process {} made disallowed syscall {}", tid, p_pid, arch.describe(syscall))
+        }
+        self.process.reason = MarkResult::Blocked(syscall, path);
+        self.kill_all(p_pid);
+    }
+    // Kills a process when we couldn't work out which syscall it made, as we can't say it's allowed
+    fn unclassified(&mut self, p_pid: libc::pid_t, tid: libc::pid_t) {
+        warn!("Couldn't tell which ABI task {} of process {} made a syscall through", tid, p_pid);
+        self.process.reason = MarkResult::Blocked(UNKNOWN_SYSCALL, Some("couldn't tell which ABI the syscall was made through".to_owned()));
+        self.kill_all(p_pid);
+    }
+    // Checks the path of a syscall that opens a file, if the policy restricts them
+    fn check_path(&self, tid: libc::pid_t, arch: Arch, syscall: u64, args: &[u64; 6]) -> Result<(), String> {
+        let paths = match self.policy.paths {
+            Some(ref paths) => paths,
+            None => return Ok(())
+        };
+        match opened(tid, arch, syscall, args) {
+            Some(Ok((dirfd, addr, flags))) => match syscall::read_string(tid, addr) {
+                Some(path) => paths.check(dirfd, Path::new(OsStr::from_bytes(&path)), flags),
+                None => Err(format!("unreadable path at {:#x}", addr))
+            },
+            Some(Err(e)) => Err(e),
+            None => Ok(())
+        }
+    }
+    // Carries out the policy for a task stopped on its way into a syscall, returning what was
+    // done. Only the first execve of the root process, which starts the submission, is let
+    // through without being allowed.
+    fn check(&mut self, p_pid: libc::pid_t, tid: libc::pid_t, arch: Arch, syscall: u64, args: &[u64; 6],
+             spawned: &mut bool) -> Action {
+        if arch == Arch::X86_64 && syscall == libc::SYS_execve as u64 && tid == p_pid && !*spawned {
+            *spawned = true;
+            return Action::Allow;
+        }
+        let action = match arch {
+            Arch::X86_64 => self.policy.native.get(&syscall).cloned(),
+            Arch::I386 => self.policy.compat.get(&syscall).cloned(),
+            Arch::Other(_) => None
+        };
+        let action = action.unwrap_or(self.policy.unlisted);
+        if action == Action::Allow || action == Action::Log {
+            if let Err(path) = self.check_path(tid, arch, syscall, args) {
+                self.block(p_pid, tid, arch, syscall, Some(path));
+                return Action::Kill;
+            }
+        }
+        match action {
+            Action::Allow => {},
+            Action::Log => info!("Task {} of process {} made syscall {}", tid, p_pid, arch.describe(syscall)),
+            Action::Errno(errno) => {
+                debug!("Failing syscall {} of task {} with errno {}", arch.describe(syscall), tid, errno);
+                syscall::fail(tid, errno);
+            },
+            // KILL IT WITH FIRE
+            Action::Kill => self.block(p_pid, tid, arch, syscall, None)
+        }
+        action
+    }
+    fn memory_exceeded(&self) -> bool {
+        match self.process.m_mem {
+            Some(mem) => self.oom || self.process.peak_mem > mem,
+            None => false
+        }
+    }
+    fn record(&mut self, ru: &libc::rusage, start: time::Instant) {
+        self.process.peak_mem = ru.ru_maxrss as u64;
+        self.process.cpu_time = Duration::new(ru.ru_utime.tv_sec as u64, ru.ru_utime.tv_usec as u32 * 1000);
+        self.process.wall_time = start.elapsed();
+    }
+    // Kills the whole process group if it is still running once the wall clock limit passes.
+    // Dropping the returned sender cancels the watchdog.
+    fn watchdog(&self, p_pid: libc::pid_t, timed_out: &Arc<AtomicBool>) -> mpsc::Sender<()> {
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        if let Some(limit) = self.process.m_wall {
+            let timed_out = timed_out.clone();
+            thread::spawn(move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(limit) {
+                    warn!("Process {} passed its wall clock limit of {:?}", p_pid, limit);
+                    timed_out.store(true, Ordering::SeqCst);
+                    unsafe { libc::kill(-p_pid, libc::SIGKILL) };
+                }
+            });
+        }
+        cancel
+    }
+    // How the root process ended, once it has
+    fn finished(&mut self, status: i32, timed_out: bool) -> MarkResult {
+        if let MarkResult::Blocked(..) = self.process.reason {
+            return self.process.reason.clone();
+        }
+        if unsafe { libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS } {
+            // Killed by the seccomp filter, so the kernel doesn't tell us which syscall
+            warn!("Process {} was killed by the seccomp filter", self.process.pid.unwrap_or(0));
+            return filtered();
+        }
+        if self.crowded {
+            return MarkResult::RTE;
+        }
+        if timed_out {
+            return MarkResult::TLE;
+        }
+        if status == 0 {
+            return if self.memory_exceeded() { MarkResult::MLE } else { MarkResult::RTE };
+        }
+        if self.memory_exceeded() {
+            return MarkResult::MLE;
+        }
+        if status == libc::SIGKILL || status == libc::SIGXCPU {
+            return MarkResult::TLE;
+        }
+        MarkResult::RTE
     }
     pub fn monitor(&mut self) {
-        use std::{ mem, thread, time };
-        thread::sleep(time::Duration::from_millis(100));
+        use std::mem;
+        thread::sleep(Duration::from_millis(100));
 
         let mut ru: libc::rusage = unsafe { mem::zeroed() };
-        let mut first = true;
-        let mut entering = true;
-        let mut spawned = false;
         let mut status = 0;
-        let mut signal = 0;
-        let mut pid = if let Some(p) = self.process.pid {
+        let mut spawned = false;
+        let p_pid = if let Some(p) = self.process.pid {
             p
         } else { return; };
-        let p_pid = pid;
+        self.tasks.insert(p_pid, Task::default());
+
+        let start = time::Instant::now();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let _cancel = self.watchdog(p_pid, &timed_out);
 
         loop {
-            unsafe { 
-                pid = libc::wait4(-p_pid, &mut status, libc::__WALL, &mut ru);
-
-                let mut syscall = false;
-
-                if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) || status == 0 {
-                    if pid == p_pid || first {
-                        self.kill_children();
-                        if let MarkResult::Blocked(_) = self.process.reason { return; }
-                        else {
-                            if status != 0 {
-                                if status == libc::SIGKILL || status == libc::SIGXCPU {
-                                    self.process.reason = MarkResult::TLE;
-                                    return;
-                                }
-                                self.process.reason = MarkResult::RTE;
-                                return;
-                            }
-                            break;
-                        }
+            unsafe {
+                let pid = libc::wait4(-p_pid, &mut status, libc::__WALL, &mut ru);
+                if pid < 0 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
                     }
-                    self.children.remove(&pid);
-                }
-                if first {
-                    libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, libc::PTRACE_O_TRACESYSGOOD
-                                 | libc::PTRACE_O_TRACEEXIT | libc::PTRACE_O_EXITKILL | libc::PTRACE_O_TRACECLONE
-                                 | libc::PTRACE_O_TRACEFORK | libc::PTRACE_O_TRACEVFORK);
+                    // Nothing left to wait for, which only happens if the root vanished under us
+                    error!("Lost track of process {}: {}", p_pid, e);
+                    self.kill_all(p_pid);
+                    self.record(&ru, start);
+                    self.process.reason = self.finished(0, timed_out.load(Ordering::SeqCst));
+                    return;
                 }
-                if libc::WSTOPSIG(status) == (0x80 | libc::SIGTRAP) {
-                    syscall = true;
-                    let mut regs: libc::user_regs_struct = mem::zeroed();
-                    libc::ptrace(libc::PTRACE_GETREGS, pid, 0, &mut regs);
-                    if regs.orig_rax == libc::SYS_execve as u64 && !spawned {
-                        if !entering {
-                            spawned = true;
+
+                if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                    self.tasks.remove(&pid);
+                    if pid != p_pid {
+                        // A forked process the filter killed, which the root may not even notice
+                        if libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS {
+                            warn!("Task {} of process {} was killed by the seccomp filter", pid, p_pid);
+                            self.process.reason = filtered();
+                            self.kill_all(p_pid);
                         }
-                        entering = !entering;
-                        libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, 0);
                         continue;
                     }
-                    if entering && !self.handlers.contains(&regs.orig_rax) {
-                        // KILL IT WITH FIRE
-                        self.process.reason = MarkResult::Blocked(regs.orig_rax);
-                        libc::kill(p_pid, libc::SIGKILL);
+                    self.kill_all(p_pid);
+                    Self::reap(p_pid);
+                    self.record(&ru, start);
+                    self.process.exit_code = if libc::WIFEXITED(status) { Some(libc::WEXITSTATUS(status)) } else { None };
+                    let blocked = if let MarkResult::Blocked(..) = self.process.reason { true } else { false };
+                    if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 && !blocked {
+                        break;
+                    }
+                    self.process.reason = self.finished(status, timed_out.load(Ordering::SeqCst));
+                    return;
+                }
+                if !libc::WIFSTOPPED(status) {
+                    continue;
+                }
+
+                let stop = libc::WSTOPSIG(status);
+                let event = (status >> 16) & 0xffff;
+                let mut signal = 0;
+                // The filter decides when we need to look at a syscall, unless we're stopping at all of them
+                let mut request = match self.process.sandbox {
+                    Sandbox::Ptrace => libc::PTRACE_SYSCALL,
+                    Sandbox::SeccompTrace | Sandbox::SeccompKill => libc::PTRACE_CONT
+                };
+                let new = !self.tasks.get(&pid).map_or(false, |t| t.started);
+                if new && stop == libc::SIGSTOP {
+                    // The stop every tracee starts with, which the root sends itself. New tasks
+                    // inherit our options, so they only have to be set once.
+                    if pid == p_pid {
+                        let seccomp = if self.process.sandbox == Sandbox::Ptrace { 0 } else { seccomp::PTRACE_O_TRACESECCOMP };
+                        libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, libc::PTRACE_O_TRACESYSGOOD
+                                     | libc::PTRACE_O_TRACEEXIT | libc::PTRACE_O_EXITKILL | libc::PTRACE_O_TRACECLONE
+                                     | libc::PTRACE_O_TRACEFORK | libc::PTRACE_O_TRACEVFORK | libc::PTRACE_O_TRACEEXEC
+                                     | seccomp);
                     }
-                    entering = !entering;
-                } else {
-                    match libc::WSTOPSIG(status) {
-                        libc::SIGTRAP => {
-                            match status >> 16 {
-                                libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK => {
-                                    let mut pid = 0u64;
-                                    libc::ptrace(libc::PTRACE_GETEVENTMSG, pid, 0, &mut pid as *mut u64 as usize);
-                                    self.children.insert(pid as i32);
+                    self.tasks.entry(pid).or_insert_with(Task::default).started = true;
+                } else if stop == (0x80 | libc::SIGTRAP) {
+                    let current = {
+                        let task = self.tasks.entry(pid).or_insert_with(Task::default);
+                        task.started = true;
+                        task.current.take()
+                    };
+                    // Older kernels can't tell us, so we have to keep track of entries and exits ourselves
+                    let info = syscall::info(pid).unwrap_or_else(|| syscall::from_regs(pid, current.is_none()));
+                    match info {
+                        syscall::Stop::Entry { arch, nr, args } => {
+                            let action = self.check(p_pid, pid, arch, nr, &args, &mut spawned);
+                            if action == Action::Kill {
+                                continue;
+                            }
+                            if let Some(task) = self.tasks.get_mut(&pid) {
+                                task.current = Some((arch, nr));
+                                if let Action::Errno(errno) = action {
+                                    task.failing = Some(errno);
+                                }
+                            }
+                        },
+                        syscall::Stop::Unclassified => {
+                            self.unclassified(p_pid, pid);
+                            continue;
+                        },
+                        syscall::Stop::Exit { rval } => {
+                            // The kernel reports a skipped syscall as ENOSYS
+                            let failing = self.tasks.get_mut(&pid).and_then(|task| task.failing.take());
+                            if let Some(errno) = failing {
+                                syscall::fail(pid, errno);
+                            }
+                            let allocation = current.map_or(false, |(arch, nr)| allocates(arch, nr));
+                            if allocation && rval == -(libc::ENOMEM as i64) {
+                                // Allocations fail rather than kill the process when they hit RLIMIT_AS or RLIMIT_DATA
+                                self.oom = true;
+                            }
+                        },
+                        syscall::Stop::None => {}
+                    }
+                } else if stop == libc::SIGTRAP && event != 0 {
+                    let mut message: libc::c_ulong = 0;
+                    libc::ptrace(libc::PTRACE_GETEVENTMSG, pid, 0, &mut message as *mut libc::c_ulong);
+                    match event {
+                        libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK | libc::PTRACE_EVENT_CLONE => {
+                            // The new task may already have reported its first stop
+                            self.tasks.entry(message as libc::pid_t).or_insert_with(Task::default);
+                            if let Some(max) = self.policy.threads {
+                                if self.tasks.len() > max {
+                                    warn!("Process {} went over its limit of {} tasks", p_pid, max);
+                                    self.crowded = true;
+                                    self.kill_all(p_pid);
+                                    continue;
+                                }
+                            }
+                        },
+                        libc::PTRACE_EVENT_EXEC => {
+                            // Any other threads are gone, and if a thread other than the leader
+                            // called execve it has now taken over the leader's tid
+                            let former = message as libc::pid_t;
+                            if former != pid {
+                                self.tasks.remove(&former);
+                            }
+                            let task = self.tasks.entry(pid).or_insert_with(Task::default);
+                            task.started = true;
+                            // The execve's exit stop is still to come when we stop at every syscall
+                            if self.process.sandbox == Sandbox::Ptrace {
+                                task.current = Some((Arch::X86_64, libc::SYS_execve as u64));
+                            }
+                        },
+                        seccomp::PTRACE_EVENT_SECCOMP => {
+                            // Only syscalls that are disallowed, logged or have their arguments checked
+                            // make it here. Without the kernel's help, the filter's arch check says
+                            // how to read the registers.
+                            let info = syscall::info(pid).unwrap_or_else(|| match message as u32 {
+                                seccomp::TRACE_NATIVE => syscall::regs_as(pid, Arch::X86_64),
+                                seccomp::TRACE_COMPAT => syscall::regs_as(pid, Arch::I386),
+                                _ => syscall::Stop::Unclassified
+                            });
+                            match info {
+                                syscall::Stop::Entry { arch, nr, args } => {
+                                    let action = self.check(p_pid, pid, arch, nr, &args, &mut spawned);
+                                    if action == Action::Kill {
+                                        continue;
+                                    }
+                                    // Stop once more on the way out to see whether the allocation failed
+                                    if allocates(arch, nr) && self.process.m_mem.is_some() {
+                                        if let Some(task) = self.tasks.get_mut(&pid) {
+                                            task.current = Some((arch, nr));
+                                        }
+                                        request = libc::PTRACE_SYSCALL;
+                                    }
+                                },
+                                syscall::Stop::Unclassified => {
+                                    self.unclassified(p_pid, pid);
+                                    continue;
                                 },
-                                _ =>  {}
+                                _ => {}
                             }
                         },
-                        x => signal = x
+                        _ => {}
                     }
+                } else if stop != libc::SIGTRAP {
+                    // Anything else is a real signal for the task, so pass it on
+                    signal = stop;
                 }
-                libc::ptrace(if syscall { libc::PTRACE_SYSCALL } else { libc::PTRACE_CONT }, pid, 0, if first { 0 } else { signal });
-                first = false;
+                libc::ptrace(request, pid, 0, signal);
             }
         }
+        if self.memory_exceeded() {
+            self.process.reason = MarkResult::MLE;
+            return;
+        }
         self.process.reason = MarkResult::Success(ru.ru_utime.tv_sec as i32, ru.ru_utime.tv_usec as i32);
+        debug!("Process {} finished in {:?} CPU, {:?} wall", p_pid, self.process.cpu_time, self.process.wall_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ env, fs, process };
+    use std::process::Command;
+
+    // Makes a socket from a second thread
+    const THREAD: &str = r#"
+#include <pthread.h>
+#include <sys/socket.h>
+static void *run(void *arg) { (void)arg; socket(AF_INET, SOCK_STREAM, 0); return 0; }
+int main(void) { pthread_t t; pthread_create(&t, 0, run, 0); pthread_join(t, 0); return 0; }
+"#;
+
+    // Makes a socket from a forked child, which the parent waits on
+    const FORK: &str = r#"
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+int main(void) { pid_t pid = fork(); if (pid == 0) { socket(AF_INET, SOCK_STREAM, 0); _exit(0); } waitpid(pid, 0, 0); return 0; }
+"#;
+
+    // Starts a thread and a child that only do what's allowed
+    const ALLOWED: &str = r#"
+#include <pthread.h>
+#include <sys/wait.h>
+#include <unistd.h>
+static void *run(void *arg) { return arg; }
+int main(void) {
+    pthread_t t; pthread_create(&t, 0, run, 0); pthread_join(t, 0);
+    pid_t pid = fork(); if (pid == 0) _exit(0); waitpid(pid, 0, 0);
+    return 0;
+}
+"#;
+
+    // Writes to /dev/null and reads from /dev/urandom, as runtimes do
+    const DEVICES: &str = r#"
+#include <fcntl.h>
+#include <unistd.h>
+int main(void) {
+    char c = 0;
+    int null = open("/dev/null", O_WRONLY), random = open("/dev/urandom", O_RDONLY);
+    return null < 0 || random < 0 || write(null, &c, 1) != 1 || read(random, &c, 1) != 1;
+}
+"#;
+
+    const SANDBOXES: &[Sandbox] = &[Sandbox::Ptrace, Sandbox::SeccompTrace, Sandbox::SeccompKill];
+
+    // A test's own directory, removed along with everything in it once the test is done
+    struct TestDir(PathBuf);
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Compiles `source` into a directory of its own. These tests need cc, and fail without it
+    // rather than passing without testing anything.
+    fn build(name: &str, source: &str) -> (TestDir, PathBuf) {
+        let dir = TestDir(env::temp_dir().join(format!("pcs_judge_test_{}_{}", process::id(), name)));
+        fs::create_dir_all(&dir.0).unwrap();
+        let src = dir.0.join(format!("{}.c", name));
+        let exe = dir.0.join(name);
+        fs::write(&src, source).unwrap();
+        let status = Command::new("cc").arg("-pthread").arg("-o").arg(&exe).arg(&src).status()
+            .expect("Couldn't run cc, which is needed to build the test programs");
+        assert!(status.success(), "{} didn't compile", name);
+        (dir, exe)
+    }
+
+    // Enough for glibc to start threads and fork, but not to make sockets
+    fn policy() -> Policy {
+        let mut policy = Policy::helper();
+        let threads: BTreeMap<String, Action> = ["clone", "clone3", "wait4", "gettid", "sched_yield"].iter()
+            .map(|name| (name.to_string(), Action::Allow))
+            .collect();
+        policy.add(&threads, &BTreeMap::new()).unwrap();
+        policy
+    }
+
+    fn judge(exe: &Path, sandbox: Sandbox) -> MarkResult {
+        let args: &[&str] = &[];
+        let process = Process::new(exe.to_str().unwrap(), args, Some(5), None).unwrap();
+        finish(process, sandbox, &policy())
+    }
+
+    fn finish(mut process: Process, sandbox: Sandbox, policy: &Policy) -> MarkResult {
+        process.m_wall = Some(Duration::from_secs(10));
+        process.sandbox(sandbox, policy);
+        process.run().unwrap();
+        unsafe {
+            libc::close(process.stdin);
+            libc::close(process.stdout);
+        }
+        {
+            let mut debugger = Debugger::new(&mut process);
+            debugger.policy(policy);
+            debugger.monitor();
+        }
+        process.reason
+    }
+
+    fn blocked(name: &str, source: &str) {
+        let (_dir, exe) = build(name, source);
+        for &sandbox in SANDBOXES {
+            match judge(&exe, sandbox) {
+                MarkResult::Blocked(..) => {},
+                x => panic!("{} under {:?} gave {:?} instead of Blocked", name, sandbox, x)
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_syscalls_from_threads() {
+        blocked("thread", THREAD);
+    }
+
+    #[test]
+    fn blocks_syscalls_from_forked_children() {
+        blocked("fork", FORK);
+    }
+
+    #[test]
+    fn allows_threads_and_children() {
+        let (_dir, exe) = build("allowed", ALLOWED);
+        for &sandbox in SANDBOXES {
+            match judge(&exe, sandbox) {
+                MarkResult::Success(..) => {},
+                x => panic!("allowed under {:?} gave {:?} instead of Success", sandbox, x)
+            }
+        }
+    }
+
+    #[test]
+    fn jail_has_devices() {
+        let (_dir, exe) = build("devices", DEVICES);
+        let workspace = isolate::Workspace::new(exe.parent().unwrap(), 0).unwrap();
+        fs::copy(&exe, workspace.dir.join("devices")).unwrap();
+        let mounts: Vec<String> = isolate::MOUNTS.iter().map(|&m| m.to_owned()).collect();
+        let args: &[&str] = &[];
+        let mut process = Process::new("/work/devices", args, Some(5), None).unwrap();
+        process.jail(Jail::new(&workspace, &mounts).unwrap());
+        match finish(process, Sandbox::SeccompTrace, &Policy::compiler()) {
+            MarkResult::Success(..) => {},
+            x => panic!("devices in the jail gave {:?} instead of Success", x)
+        }
     }
 }
@@ -0,0 +1,99 @@
+extern crate libc;
+
+use std::{ fs, io, thread, time::Duration };
+
+use super::{ checker::Scratch, debugger, JudgeError, MarkResult, Settings };
+
+// A program that talks to the submission over its stdin and stdout.
+// It is run as `<exe> <input> <expected> <verdict>`, and accepts the answer by exiting
+// successfully. Anything it writes to the verdict file is reported as the message.
+#[derive(Clone, Debug)]
+pub struct Interactor {
+    pub exe:        String,
+    // Its own limits, in seconds of CPU time and KiB
+    pub max_time:   Option<u64>,
+    pub max_memory: Option<u64>,
+    // The sandbox profile it runs under, if it needs more than the helper syscalls
+    pub profile:    Option<String>
+}
+
+fn pipe() -> io::Result<[i32;2]> {
+    let mut fds = [0i32;2];
+    // Close on exec, so that each child only keeps the ends it dup2s onto stdin/stdout
+    if unsafe { libc::pipe2(&mut fds as *mut [i32] as _, libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fds)
+}
+
+// The tracer has to be the thread that forked, so each process gets its own thread
+fn spawn(mut process: debugger::Process, policy: debugger::Policy) -> thread::JoinHandle<Result<debugger::Process, JudgeError>> {
+    thread::spawn(move || {
+        process.run()?;
+        { // Give debugger an explicit lifetime
+            let mut debugger = debugger::Debugger::new(&mut process);
+            debugger.policy(&policy);
+            debugger.monitor();
+        }
+        Ok(process)
+    })
+}
+
+// Runs `submission` against the interactor, which runs under `helper`, returning the finished
+// submission and its verdict
+pub fn run(settings: &Settings, interactor: &Interactor, helper: &debugger::Policy, mut submission: debugger::Process,
+           policy: &debugger::Policy, case_in: &str, case_out: &str) -> Result<(debugger::Process, MarkResult), JudgeError> {
+    let mut scratch = Scratch::new();
+    let (i, e) = scratch.write("input", case_in)
+        .and_then(|i| scratch.write("expected", case_out).map(|e| (i, e)))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let verdict = scratch.path("verdict");
+    let v = verdict.to_string_lossy().into_owned();
+
+    let mut process = debugger::Process::new(interactor.exe.as_str(), &[i.as_str(), e.as_str(), v.as_str()],
+                                             interactor.max_time, interactor.max_memory)?;
+    process.sandbox(settings.sandbox, helper);
+
+    let to_interactor = pipe().map_err(JudgeError::Spawn)?;
+    let to_submission = match pipe() {
+        Ok(fds) => fds,
+        Err(e) => {
+            unsafe {
+                libc::close(to_interactor[0]);
+                libc::close(to_interactor[1]);
+            }
+            return Err(JudgeError::Spawn(e));
+        }
+    };
+    submission.stdio(to_submission[0], to_interactor[1]);
+    // The interactor spends most of its time waiting on the submission
+    process.m_wall = submission.m_wall.map(|w| w + Duration::from_secs(1));
+    process.stdio(to_interactor[0], to_submission[1]);
+
+    let interactor_thread = spawn(process, helper.clone());
+    let submission_thread = spawn(submission, policy.clone());
+    let joined = |t: thread::JoinHandle<Result<debugger::Process, JudgeError>>| {
+        t.join().unwrap_or_else(|_| Err(JudgeError::Spawn(io::Error::new(io::ErrorKind::Other, "Tracer thread panicked"))))
+    };
+    let process = joined(interactor_thread);
+    let submission = joined(submission_thread)?;
+    let process = process?;
+
+    let message = fs::read_to_string(&verdict).unwrap_or_default();
+    let result = match (&submission.reason, &process.reason) {
+        (&MarkResult::Blocked(x, ref path), _) => MarkResult::Blocked(x, path.clone()),
+        (&MarkResult::TLE, _) => MarkResult::TLE,
+        (&MarkResult::MLE, _) => MarkResult::MLE,
+        // Exiting unsuccessfully is how the interactor rejects an answer, and the submission
+        // usually dies of a broken pipe once it has
+        (_, &MarkResult::RTE) if process.exit_code.is_some() => MarkResult::Fail(message),
+        (&MarkResult::RTE, &MarkResult::Success(_, _)) => MarkResult::RTE,
+        (&MarkResult::Success(s, ns), &MarkResult::Success(_, _)) => MarkResult::Success(s, ns),
+        // Crashing, running out of time or memory, or being blocked is the interactor's fault
+        (_, x) => {
+            error!("Interactor {} failed: {:?}", interactor.exe, x);
+            MarkResult::IE(format!("Interactor failed: {:?}", x))
+        }
+    };
+    Ok((submission, result))
+}
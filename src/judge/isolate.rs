@@ -0,0 +1,158 @@
+extern crate libc;
+
+use std::{ fs, io, ptr };
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{ Path, PathBuf };
+
+fn cstr(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+// A fresh directory for a single submission, removed along with everything in it when dropped
+pub struct Workspace {
+    pub dir:    PathBuf,
+    // The jail's /tmp, which compilers need to write to
+    pub tmp:    PathBuf,
+    pub root:   PathBuf
+}
+impl Workspace {
+    pub fn new(parent: &Path, batch: u32) -> io::Result<Workspace> {
+        let base = parent.join(batch.to_string());
+        if base.exists() {
+            fs::remove_dir_all(&base)?;
+        }
+        let dir = base.join("work");
+        let tmp = base.join("tmp");
+        let root = base.join("root");
+        fs::create_dir_all(&dir)?;
+        fs::create_dir_all(&tmp)?;
+        fs::create_dir_all(&root)?;
+        Ok(Workspace { dir: dir, tmp: tmp, root: root })
+    }
+}
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if let Some(base) = self.dir.parent() {
+            if let Err(e) = fs::remove_dir_all(base) {
+                warn!("Couldn't clean up {:?}: {}", base, e);
+            }
+        }
+    }
+}
+
+// Where the work directory is inside the jail
+pub const WORK_DIR: &str = "/work";
+
+// The namespaces a jailed process is created in. unshare would leave the caller outside the new
+// PID namespace, so the process has to be created in them with clone.
+pub const NAMESPACES: libc::c_int = libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET | libc::CLONE_NEWIPC | libc::CLONE_NEWUTS;
+
+// What's bind mounted read-only into the jail unless the configuration says otherwise
+pub const MOUNTS: &[&str] = &["/bin", "/lib", "/lib64", "/usr", "/etc/alternatives", "/etc/ld.so.cache"];
+
+// Devices every jail has, since runtimes and compilers expect them. They're bound one at a time,
+// as binding all of /dev would hand over the judge's terminals and disks too.
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/urandom"];
+
+// Everything the child needs to move itself into new namespaces and a minimal read-only root.
+// It is all prepared up front, since we can't allocate safely between fork and exec.
+#[derive(Clone)]
+pub struct Jail {
+    root:       CString,
+    // (source, target, flags) for each bind mount, where the flags are those it's remounted with
+    mounts:     Vec<(CString, CString, libc::c_ulong)>,
+    uid_map:    CString,
+    gid_map:    CString
+}
+impl Jail {
+    // Makes somewhere under the new root to mount `source` on
+    fn target(workspace: &Workspace, source: &Path) -> io::Result<CString> {
+        let target = workspace.root.join(source.strip_prefix("/").unwrap_or(source));
+        if source.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::File::create(&target)?;
+        }
+        Ok(cstr(&target))
+    }
+    pub fn new(workspace: &Workspace, mounts: &[String]) -> io::Result<Jail> {
+        let mut binds = Vec::new();
+        for mount in mounts {
+            let source = Path::new(mount);
+            if source.exists() {
+                let target = Self::target(workspace, source)?;
+                binds.push((cstr(source), target, libc::MS_NOSUID | libc::MS_NODEV | libc::MS_RDONLY));
+            }
+        }
+        // Devices can be written to, but nothing on them can be run
+        for device in DEVICES {
+            let source = Path::new(device);
+            if source.exists() {
+                let target = Self::target(workspace, source)?;
+                binds.push((cstr(source), target, libc::MS_NOSUID | libc::MS_NOEXEC));
+            }
+        }
+        let work = workspace.root.join("work");
+        let tmp = workspace.root.join("tmp");
+        fs::create_dir_all(&work)?;
+        fs::create_dir_all(&tmp)?;
+        binds.push((cstr(&workspace.dir), cstr(&work), libc::MS_NOSUID | libc::MS_NODEV));
+        binds.push((cstr(&workspace.tmp), cstr(&tmp), libc::MS_NOSUID | libc::MS_NODEV));
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok(Jail {
+            root:       cstr(&workspace.root),
+            mounts:     binds,
+            uid_map:    CString::new(format!("0 {} 1", uid)).unwrap(),
+            gid_map:    CString::new(format!("0 {} 1", gid)).unwrap()
+        })
+    }
+    unsafe fn write(file: &[u8], bytes: &[u8]) -> bool {
+        let fd = libc::open(file.as_ptr() as _, libc::O_WRONLY);
+        if fd < 0 {
+            return false;
+        }
+        let written = libc::write(fd, bytes.as_ptr() as _, bytes.len());
+        libc::close(fd);
+        written == bytes.len() as isize
+    }
+    unsafe fn bind(source: &CString, target: &CString, flags: libc::c_ulong) -> bool {
+        libc::mount(source.as_ptr(), target.as_ptr(), ptr::null(), libc::MS_BIND | libc::MS_REC, ptr::null()) == 0 &&
+            libc::mount(ptr::null(), target.as_ptr(), ptr::null(), libc::MS_REMOUNT | libc::MS_BIND | flags, ptr::null()) == 0
+    }
+    // Must only be called in a child created in `NAMESPACES`. The work directory ends up as the writable `/work`,
+    // beside a writable `/tmp` and the devices in `DEVICES`.
+    pub unsafe fn enter(&self) -> bool {
+        if !Self::write(b"/proc/self/setgroups\0", b"deny") ||
+            !Self::write(b"/proc/self/uid_map\0", self.uid_map.as_bytes()) ||
+            !Self::write(b"/proc/self/gid_map\0", self.gid_map.as_bytes()) {
+            return false;
+        }
+        // Keep our mounts from propagating back to the judge
+        if libc::mount(ptr::null(), b"/\0".as_ptr() as _, ptr::null(), libc::MS_REC | libc::MS_PRIVATE, ptr::null()) != 0 {
+            return false;
+        }
+        // pivot_root needs the new root to be a mount point
+        if libc::mount(self.root.as_ptr(), self.root.as_ptr(), ptr::null(), libc::MS_BIND, ptr::null()) != 0 {
+            return false;
+        }
+        for &(ref source, ref target, flags) in self.mounts.iter() {
+            if !Self::bind(source, target, flags) {
+                return false;
+            }
+        }
+        let dot = b".\0".as_ptr() as *const libc::c_char;
+        if libc::chdir(self.root.as_ptr()) != 0 ||
+            libc::syscall(libc::SYS_pivot_root, dot, dot) != 0 ||
+            libc::umount2(dot, libc::MNT_DETACH) != 0 {
+            return false;
+        }
+        libc::mount(ptr::null(), b"/\0".as_ptr() as _, ptr::null(),
+                    libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY, ptr::null()) == 0 &&
+            libc::chdir(b"/work\0".as_ptr() as _) == 0
+    }
+}
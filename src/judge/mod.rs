@@ -1,133 +1,394 @@
 extern crate pcs_protocol;
 use pcs_protocol::MarkResult;
 
+extern crate futures;
+use futures::sync::mpsc::{ unbounded, UnboundedReceiver, UnboundedSender };
+
 extern crate libc;
 
-use std::{ ffi::CString, fs, io::{ Read, Write } };
-use std::{ path::Path, process::{ Command, Stdio } };
-use std::{ sync::mpsc, thread };
+use std::{ env, fs, io::Write };
+use std::path::{ Path, PathBuf };
+use std::{ sync::{ Arc, Mutex, mpsc }, thread, time::Duration };
 
+use super::error::JudgeError;
 use super::executor;
+use super::profile::{ self, Profile };
 
+mod checker;
 mod debugger;
+mod interactive;
+mod isolate;
+mod pipe;
+mod pool;
+mod problem;
+mod seccomp;
+mod status;
+mod store;
+mod syscall;
+
+pub use self::checker::Kind as CheckerKind;
+pub use self::debugger::{ Action, Policy, Sandbox, UNKNOWN_SYSCALL };
+pub use self::interactive::Interactor;
+pub use self::isolate::MOUNTS as JAIL_MOUNTS;
+pub use self::pool::physical_cores;
+pub use self::problem::{ Case, Problem, MANIFEST };
+pub use self::status::Status;
+pub use self::store::Store;
+pub use self::syscall::Arch;
+
+#[derive(Clone)]
+pub struct Settings {
+    pub executors:      String,
+    pub sandbox:        Sandbox,
+    // Wall clock limit as a multiple of the CPU time limit
+    pub wall_multiplier: f64,
+    // Wall clock limit for problems without a CPU time limit
+    pub default_wall:   Duration,
+    pub workers:        usize,
+    // Each worker compiles and runs submissions in its own directory under here
+    pub work_dir:       String,
+    // Where problem packages are found, see problem::Cache
+    pub problems:       String,
+    // Where test data sent by the server is kept, and how many bytes it can take up
+    pub store:          String,
+    pub store_budget:   u64,
+    // Paths bind mounted read-only into the jail, or None to run without one
+    pub jail:           Option<Vec<String>>
+}
+
+// Hands submissions to the workers, keeping count of how many are waiting
+#[derive(Clone)]
+pub struct Queue {
+    send:   mpsc::Sender<ToMark>,
+    status: Arc<Status>,
+    store:  Arc<Store>
+}
+impl Queue {
+    pub fn send(&self, mark: ToMark) -> Result<(), JudgeError> {
+        self.status.queue();
+        self.send.send(mark).map_err(|_| {
+            self.status.dequeue();
+            JudgeError::Disconnected
+        })
+    }
+    pub fn status(&self) -> &Arc<Status> {
+        &self.status
+    }
+    pub fn store(&self) -> &Arc<Store> {
+        &self.store
+    }
+}
 
-pub fn setup(dir: String) -> (thread::JoinHandle<()>, mpsc::Sender<ToMark>, mpsc::Receiver<ToSend>) {
+// Workers take submissions from a shared queue, and every result carries its batch and case
+// so they can be sent back in whatever order they finish.
+// Results go back over a futures channel so the event loop is woken as soon as one is ready
+pub fn setup(settings: Settings) -> Result<(Vec<thread::JoinHandle<()>>, Queue, UnboundedReceiver<ToSend>), JudgeError> {
     let (s_ptj, r_ptj) = mpsc::channel();
-    let (s_jtp, r_jtp) = mpsc::channel();
-    (thread::spawn(move || run(dir, s_jtp, r_ptj)), s_ptj, r_jtp)
+    let (s_jtp, r_jtp) = unbounded();
+    let r_ptj = Arc::new(Mutex::new(r_ptj));
+    let status = Arc::new(Status::new(settings.workers.max(1)));
+    let problems = Arc::new(problem::Cache::new(&settings.problems));
+    let store = Arc::new(Store::open(&settings.store, settings.store_budget)?);
+    let cpus = pool::cpus();
+    let workers = (0..settings.workers.max(1)).map(|id| -> Result<thread::JoinHandle<()>, JudgeError> {
+        let dir = Path::new(&settings.work_dir).join(id.to_string());
+        fs::create_dir_all(&dir)?;
+        let worker = Worker {
+            id:         id,
+            dir:        dir,
+            settings:   settings.clone(),
+            sender:     s_jtp.clone(),
+            status:     status.clone(),
+            problems:   problems.clone(),
+            store:      store.clone()
+        };
+        let recver = r_ptj.clone();
+        let cpu = cpus[id % cpus.len()];
+        thread::Builder::new().name(format!("judge-{}", id)).spawn(move || {
+            pool::pin(cpu);
+            run(worker, recver)
+        }).map_err(JudgeError::Spawn)
+    }).collect::<Result<Vec<_>, JudgeError>>()?;
+    Ok((workers, Queue { send: s_ptj, status: status, store: store }, r_jtp))
+}
+
+// Everything a worker judges with besides the submission itself
+struct Worker {
+    id:         usize,
+    // Each submission gets a workspace under here
+    dir:        PathBuf,
+    settings:   Settings,
+    sender:     UnboundedSender<ToSend>,
+    status:     Arc<Status>,
+    problems:   Arc<problem::Cache>,
+    store:      Arc<Store>
+}
+
+// What a submission is judged against
+pub enum ProblemRef {
+    // Limits and test data sent along with the submission
+    Inline(Arc<Problem>),
+    // A package, by id and version, that the workers load from the problems directory
+    Package(String, u32),
+    // Limits sent with the submission, with the test data for each case already in the store
+    Stored(Arc<Problem>, store::Pinned)
 }
 
 pub struct ToMark {
     pub batch:      u32,
     pub answer:     String,
     pub lang:       String,
-    pub max_time:   Option<u64>,
-    pub case_in:    Vec<String>,
-    pub case_out:   Vec<String>
+    pub problem:    ProblemRef,
+    // Hand the submission's output back with each result, for judging locally
+    pub keep_output: bool
 }
 
 #[derive(Clone)]
 pub struct ToSend {
     pub batch:      u32,
     pub case:       u32,
-    pub result:     MarkResult
+    pub result:     MarkResult,
+    pub cpu_time:   Duration,
+    pub wall_time:  Duration,
+    pub output:     Option<String>
 }
 
-fn run(dir: String, sender: mpsc::Sender<ToSend>, recver: mpsc::Receiver<ToMark>) {
-    let exec_dir = Path::new(&dir);
-    for input in recver.iter() {
-        // Pre-run compilation/preparing
-        let mut lang = input.lang;
-        lang.push_str(".yaml");
-        let mut exec = fs::File::open(exec_dir.join(Path::new(&lang))).unwrap();
-        let executor = executor::Executor::from_file(&mut exec);
-        let mut sub = fs::File::open(executor.filename).unwrap();
-        write!(sub, "{}", input.answer).unwrap();
-        sub.flush().unwrap();
-        if let Some(pre_exec) = executor.pre_exec {
-            let vec_args: Vec<&str> = pre_exec.split_whitespace().collect();
-            let mut cmd = Command::new(vec_args[0]).args(vec_args[1..].iter()).stdout(Stdio::piped()).spawn().unwrap();
-            match cmd.wait() {
-                Ok(exit) => if !exit.success() {
-                    let output = if let Some(mut out) = cmd.stdout {
-                        let mut s = String::new();
-                        out.read_to_string(&mut s).unwrap();
-                        s
-                    } else {
-                        "Unknown error".to_owned()
-                    };
-                    sender.send(ToSend {
-                        batch:      input.batch,
-                        case:       0,
-                        result:     MarkResult::CE(output)
-                    }).unwrap();
-                    continue;
-                },
-                Err(_) => {
-                    let output = if let Some(mut out) = cmd.stdout {
-                        let mut s = String::new();
-                        out.read_to_string(&mut s).unwrap();
-                        s
-                    } else {
-                        "Unknown error".to_owned()
-                    };
-                    sender.send(ToSend {
-                        batch:      input.batch,
-                        case:       0,
-                        result:     MarkResult::CE(output)
-                    }).unwrap();
-                    continue;
+fn batch(mut process: debugger::Process, policy: &Policy, checker: &checker::Checker,
+         case_in: &str, case_out: &str) -> Result<(debugger::Process, MarkResult, Option<String>), JudgeError> {
+    process.run()?;
+    // Either pipe filling up would stall the submission while we wait on it, so both are
+    // handled on their own threads while the debugger watches
+    let feeder = pipe::feed(process.stdin, case_in.as_bytes().to_vec());
+    let drainer = pipe::drain(process.stdout, pipe::OUTPUT_LIMIT);
+    { // Give debugger an explicit lifetime
+        let mut debugger = debugger::Debugger::new(&mut process);
+        debugger.policy(policy);
+        debugger.monitor();
+    }
+    let _ = feeder.join();
+    let output = String::from_utf8_lossy(&drainer.join().unwrap_or_default()).into_owned();
+    let result = match process.reason {
+        MarkResult::Success(s, ns) => {
+            match checker.check(case_in, case_out, &output) {
+                checker::Verdict::Accept => MarkResult::Success(s, ns),
+                checker::Verdict::Reject(message) => MarkResult::Fail(message),
+                checker::Verdict::Fault(message) => {
+                    error!("Checker failed: {}", message);
+                    MarkResult::IE(message)
                 }
             }
-        }
-        for case_num in 1..(input.case_in.len()+1) {
-            let vec_args: Vec<&str> = executor.exec.split_whitespace().collect();
-            let mut process = debugger::Process::new(vec_args[0], &vec_args[1..], input.max_time);
-            process.run();
-            let stdin = CString::new(input.case_in[case_num-1].clone()).unwrap();
-            let i = stdin.as_bytes_with_nul();
-            unsafe {
-                libc::write(process.stdin, i.as_ptr() as _, i.len());
-            }
-            { // Give debugger an explicit lifetime
-                let mut debugger = debugger::Debugger::standard(&mut process);
-                debugger.monitor();
+        },
+        ref x => x.clone()
+    };
+    Ok((process, result, Some(output)))
+}
+
+// What a special judge or interactor runs under: the helper syscalls, with the profile the
+// problem names for it laid over them
+fn helper_policy(settings: &Settings, profile: &Option<String>) -> Result<Policy, JudgeError> {
+    let mut policy = Policy::helper();
+    if let Some(ref name) = *profile {
+        let dir = Path::new(&settings.executors).join(profile::DIR);
+        let profile = Profile::load(&dir, name)?;
+        policy.add(&profile.syscalls, &profile.compat_syscalls)
+            .map_err(|e| JudgeError::Profile(dir.join(name).display().to_string(), e))?;
+        policy.threads = profile.threads;
+    }
+    Ok(policy)
+}
+
+fn send(sender: &UnboundedSender<ToSend>, result: ToSend) -> Result<(), JudgeError> {
+    sender.unbounded_send(result).map_err(|_| JudgeError::Disconnected)
+}
+
+// Limits for compiling, where the times are in seconds and CPU time is for each process the
+// compiler starts
+const COMPILE_TIME:     libc::rlim_t = 30;
+const COMPILE_WALL:     u64 = 60;
+const COMPILE_MEMORY:   libc::rlim_t = 1024 * 1024;
+// How much of the compiler's messages are sent back
+const COMPILE_OUTPUT:   usize = 64 * 1024;
+
+// execv doesn't search PATH, so find the compiler the way a shell would
+fn find_program(program: &str) -> String {
+    if program.contains('/') {
+        return program.to_owned();
+    }
+    env::var_os("PATH").and_then(|path| {
+        env::split_paths(&path).map(|dir| dir.join(program)).find(|candidate| candidate.is_file())
+    }).map_or_else(|| program.to_owned(), |found| found.to_string_lossy().into_owned())
+}
+
+// Runs the executor's `pre_exec` in the same sandbox as the submission, returning the compile
+// error if it failed
+fn compile(settings: &Settings, executor: &executor::Executor, workspace: &isolate::Workspace,
+           jail: &Option<isolate::Jail>) -> Result<Option<String>, JudgeError> {
+    let vec_args: Vec<&str> = match executor.pre_exec {
+        Some(ref pre_exec) => pre_exec.split_whitespace().collect(),
+        None => return Ok(None)
+    };
+    if vec_args.is_empty() {
+        return Ok(None);
+    }
+    let program = find_program(vec_args[0]);
+    let args: Vec<String> = vec_args[1..].iter().map(|&arg| arg.to_owned()).collect();
+    let policy = Policy::compiler();
+    let mut process = debugger::Process::new(program, &args, Some(COMPILE_TIME), Some(COMPILE_MEMORY))?;
+    process.m_wall = Some(Duration::from_secs(COMPILE_WALL));
+    process.address_space(executor.sandbox.address_space.unwrap_or(true));
+    process.merge_stderr();
+    process.sandbox(settings.sandbox, &policy);
+    match *jail {
+        Some(ref jail) => process.jail(jail.clone()),
+        None => process.cwd(&workspace.dir)
+    }
+    process.run()?;
+    unsafe { libc::close(process.stdin) };
+    let drainer = pipe::drain(process.stdout, COMPILE_OUTPUT);
+    { // Give debugger an explicit lifetime
+        let mut debugger = debugger::Debugger::new(&mut process);
+        debugger.policy(&policy);
+        debugger.monitor();
+    }
+    let output = String::from_utf8_lossy(&drainer.join().unwrap_or_default()).into_owned();
+    Ok(match process.reason {
+        MarkResult::Success(..) => None,
+        MarkResult::TLE => Some(format!("Compiling took too long\n{}", output)),
+        MarkResult::MLE => Some(format!("Compiling used too much memory\n{}", output)),
+        _ => Some(output)
+    })
+}
+
+fn mark(worker: &Worker, input: &ToMark) -> Result<(), JudgeError> {
+    let Worker { id, ref dir, ref settings, ref sender, ref status, ref problems, ref store } = *worker;
+    // Pre-run compilation/preparing
+    let exec_dir = Path::new(&settings.executors);
+    let lang = format!("{}.yaml", input.lang);
+    let executor = executor::Executor::from_file(&exec_dir.join(Path::new(&lang)))?;
+    let problem = match input.problem {
+        ProblemRef::Inline(ref problem) => problem.clone(),
+        ProblemRef::Package(ref id, version) => problems.get(id, version)?,
+        ProblemRef::Stored(ref limits, ref pinned) => {
+            let mut problem = (**limits).clone();
+            for (i, &(ref input, ref output)) in pinned.cases.iter().enumerate() {
+                problem.cases.push(Case {
+                    name:   (i + 1).to_string(),
+                    input:  store.read(input)?,
+                    output: store.read(output)?
+                });
             }
-            let mut output = String::new();
-            let mut b8 = 0u8;
-            while unsafe { libc::read(process.stdout, &mut b8 as *mut u8 as _, 1) == 1 } {
-                output.push(b8 as char);
+            Arc::new(problem)
+        }
+    };
+    let checker = problem.checker.checker(settings.sandbox, helper_policy(settings, &problem.checker_profile)?);
+    let interactor = match problem.interactor {
+        Some(ref interactor) => Some((interactor, helper_policy(settings, &interactor.profile)?)),
+        None => None
+    };
+    let mut policy = executor.policy().map_err(|e| JudgeError::Executor(lang.clone(), e))?;
+    let workspace = isolate::Workspace::new(dir, input.batch)?;
+    let jail = match settings.jail {
+        Some(ref mounts) => Some(isolate::Jail::new(&workspace, mounts)?),
+        None => None
+    };
+    // Paths are checked as the submission sees them
+    let work = match jail {
+        Some(_) => PathBuf::from(isolate::WORK_DIR),
+        None => fs::canonicalize(&workspace.dir)?
+    };
+    if let Err(e) = policy.restrict(debugger::Paths::new(work, &executor.sandbox.read_paths)) {
+        // Only the jail can keep a threaded submission to its own files
+        if jail.is_none() {
+            return Err(JudgeError::Executor(lang.clone(), format!("{}, so it has to be run in the jail", e)));
+        }
+    }
+    let max_memory = problem.max_memory.map(|kb| kb + executor.sandbox.extra_memory.unwrap_or(0) * 1024);
+    let mut sub = fs::File::create(workspace.dir.join(&executor.filename))?;
+    write!(sub, "{}", input.answer)?;
+    sub.flush()?;
+    if let Some(output) = compile(settings, &executor, &workspace, &jail)? {
+        return send(sender, ToSend {
+            batch:      input.batch,
+            case:       0,
+            result:     MarkResult::CE(output),
+            cpu_time:   Duration::default(),
+            wall_time:  Duration::default(),
+            output:     None
+        });
+    }
+    let vec_args: Vec<&str> = executor.exec.split_whitespace().collect();
+    let (program, args) = vec_args.split_first()
+        .ok_or_else(|| JudgeError::Executor(lang.clone(), "exec can't be empty".to_owned()))?;
+    for (case_num, case) in (1..).zip(problem.cases.iter()) {
+        let mut process = debugger::Process::new(*program, args, problem.max_time, max_memory)?;
+        process.address_space(executor.sandbox.address_space.unwrap_or(true));
+        process.sandbox(settings.sandbox, &policy);
+        match jail {
+            Some(ref jail) => process.jail(jail.clone()),
+            None => process.cwd(&workspace.dir)
+        }
+        // Without a limit, a submission that sleeps or waits on input would hold the worker forever
+        process.m_wall = Some(match problem.max_time {
+            Some(t) => Duration::from_millis((t as f64 * settings.wall_multiplier * 1000.0) as u64),
+            None => settings.default_wall
+        });
+        status.start(id, input.batch, case_num, process.m_wall);
+        let marked = match interactor {
+            Some((interactor, ref helper)) => interactive::run(settings, interactor, helper, process, &policy, &case.input, &case.output)
+                .map(|(process, result)| (process, result, None)),
+            None => batch(process, &policy, &*checker, &case.input, &case.output)
+        };
+        status.finish(id);
+        let (process, result, output) = marked?;
+        let result = match result {
+            MarkResult::Success(s, ns) => {
+                match problem.max_time {
+                    Some(x) if s as f64 + (ns as f64 / 1e6) > x as f64 => MarkResult::TLE,
+                    _ => MarkResult::Success(s, ns)
+                }
+            },
+            x => x
+        };
+        send(sender, ToSend {
+            batch:      input.batch,
+            case:       case_num,
+            result:     result,
+            cpu_time:   process.cpu_time,
+            wall_time:  process.wall_time,
+            output:     if input.keep_output { output } else { None }
+        })?;
+    }
+    Ok(())
+}
+
+fn run(worker: Worker, recver: Arc<Mutex<mpsc::Receiver<ToMark>>>) {
+    loop {
+        let input = match recver.lock() {
+            Ok(recver) => match recver.recv() {
+                Ok(input) => input,
+                Err(_) => break
+            },
+            Err(_) => break
+        };
+        worker.status.dequeue();
+        match mark(&worker, &input) {
+            Ok(()) => {},
+            Err(JudgeError::Disconnected) => break,
+            Err(e) => {
+                error!("Couldn't judge batch {}: {}", input.batch, e);
+                let internal = ToSend {
+                    batch:      input.batch,
+                    case:       0,
+                    result:     MarkResult::IE(e.to_string()),
+                    cpu_time:   Duration::default(),
+                    wall_time:  Duration::default(),
+                    output:     None
+                };
+                if send(&worker.sender, internal).is_err() {
+                    break;
+                }
             }
-            let result = match process.reason {
-                MarkResult::Success(s, ns) => { 
-                    match input.max_time {
-                        Some(x) => {
-                            if s as f64 + (ns as f64 / 1e6) > x as f64 {
-                                MarkResult::TLE
-                            } else {
-                                if output == input.case_out[case_num-1] {
-                                    MarkResult::Success(s, ns)
-                                } else {
-                                    MarkResult::Fail(output)
-                                }
-                            }
-                        }
-                        None => {
-                            if output == input.case_out[case_num-1] {
-                                MarkResult::Success(s, ns)
-                            } else {
-                                MarkResult::Fail(output)
-                            }
-                        }
-                    }
-                },
-                x => x
-            };
-            sender.send(ToSend {
-                batch:      input.batch,
-                case:       case_num as u32,
-                result:     result
-            }).unwrap();
         }
     }
+    info!("Judge worker stopped");
 }
@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::{ self, Read, Write };
+use std::os::unix::io::FromRawFd;
+use std::thread;
+
+// How much of a program's output is kept. Anything past this is still read, so the program
+// never stalls on a full pipe, but thrown away.
+pub const OUTPUT_LIMIT: usize = 64 * 1024 * 1024;
+
+// Writes `input` to `fd` on its own thread, then closes it so the reader sees EOF.
+// The thread owns `fd` from here on.
+pub fn feed(fd: i32, input: Vec<u8>) -> thread::JoinHandle<()> {
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    thread::spawn(move || {
+        // A program is free to exit without reading all of its input
+        let _ = file.write_all(&input);
+    })
+}
+
+// Reads `fd` until EOF on its own thread, keeping the first `limit` bytes.
+// The thread owns `fd` from here on.
+pub fn drain(fd: i32, limit: usize) -> thread::JoinHandle<Vec<u8>> {
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    thread::spawn(move || {
+        let mut kept = Vec::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let room = limit.saturating_sub(kept.len());
+                    kept.extend_from_slice(&buf[..n.min(room)]);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break
+            }
+        }
+        kept
+    })
+}
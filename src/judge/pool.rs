@@ -0,0 +1,78 @@
+extern crate libc;
+
+use std::collections::{ BTreeMap, BTreeSet };
+use std::{ fs, mem };
+
+// The CPUs this process may run on, which containers and taskset can narrow down
+fn allowed() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) == 0 {
+            let allowed: Vec<usize> = (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set)).collect();
+            if !allowed.is_empty() {
+                return allowed;
+            }
+        }
+    }
+    let online = match unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) } {
+        x if x > 0 => x as usize,
+        _ => 1
+    };
+    (0..online).collect()
+}
+
+// The (physical id, core id) of each logical CPU in /proc/cpuinfo
+fn cores() -> BTreeMap<usize, (String, String)> {
+    let mut cores = BTreeMap::new();
+    let cpuinfo = match fs::read_to_string("/proc/cpuinfo") {
+        Ok(c) => c,
+        Err(_) => return cores
+    };
+    let mut processor = None;
+    let mut physical = String::new();
+    for line in cpuinfo.lines() {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        match key {
+            "processor" => {
+                processor = value.parse::<usize>().ok();
+                physical.clear();
+            },
+            "physical id" => physical = value.to_owned(),
+            "core id" => if let Some(cpu) = processor {
+                cores.insert(cpu, (physical.clone(), value.to_owned()));
+            },
+            _ => {}
+        }
+    }
+    cores
+}
+
+// One CPU from each physical core we're allowed to run on, so that hyperthreads don't end up
+// sharing a core between two submissions and skewing their times
+pub fn cpus() -> Vec<usize> {
+    let cores = cores();
+    let mut seen = BTreeSet::new();
+    allowed().into_iter().filter(|cpu| match cores.get(cpu) {
+        Some(core) => seen.insert(core.clone()),
+        // Without a core id, each CPU is taken to be a core of its own
+        None => true
+    }).collect()
+}
+
+pub fn physical_cores() -> usize {
+    cpus().len()
+}
+
+// Pin the calling thread (and so every process it forks) to a single CPU
+pub fn pin(cpu: usize) {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            warn!("Couldn't pin worker to CPU {}", cpu);
+        }
+    }
+}
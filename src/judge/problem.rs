@@ -0,0 +1,172 @@
+extern crate serde_yaml;
+
+extern crate tar;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use super::{ checker, interactive::Interactor, JudgeError };
+
+pub const MANIFEST: &str = "manifest.yaml";
+
+static UNPACKS: AtomicUsize = AtomicUsize::new(0);
+
+// The manifest at the root of a problem package
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    // CPU time limit in seconds
+    time:       Option<u64>,
+    // Memory limit in MiB
+    memory:     Option<u64>,
+    #[serde(default)]
+    checker:    String,
+    // Relative to the package, like a special judge
+    interactor: Option<String>,
+    // The interactor's own limits, in seconds and MiB. Its time defaults to the submission's.
+    interactor_time:    Option<u64>,
+    interactor_memory:  Option<u64>,
+    // Sandbox profiles for the special judge and interactor, from the executors' profiles
+    checker_profile:    Option<String>,
+    interactor_profile: Option<String>,
+    // Each name has a `<name>.in` and `<name>.out` relative to the package
+    cases:      Vec<String>,
+    #[serde(default)]
+    subtasks:   Vec<Subtask>
+}
+
+// Subtasks are scored by the server, the judge only makes sure they're made of real cases
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subtask {
+    pub name:   String,
+    pub points: u32,
+    pub cases:  Vec<String>
+}
+
+#[derive(Clone, Debug)]
+pub struct Case {
+    pub name:   String,
+    pub input:  String,
+    pub output: String
+}
+
+// Everything a submission is judged against
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub max_time:   Option<u64>,
+    pub max_memory: Option<u64>, // KiB
+    pub checker:    checker::Kind,
+    // The sandbox profile a special judge runs under, if it needs more than the helper syscalls
+    pub checker_profile: Option<String>,
+    pub interactor: Option<Interactor>,
+    pub cases:      Vec<Case>,
+    pub subtasks:   Vec<Subtask>
+}
+impl Problem {
+    // Loads the unpacked package in `dir`
+    pub fn from_dir(dir: &Path) -> Result<Problem, JudgeError> {
+        let bad = |e: String| JudgeError::Problem(dir.display().to_string(), e);
+        let file = fs::File::open(dir.join(MANIFEST)).map_err(|e| bad(format!("Couldn't open {}: {}", MANIFEST, e)))?;
+        let manifest: Manifest = serde_yaml::from_reader(file).map_err(|e| bad(e.to_string()))?;
+        let checker = match manifest.checker.parse().map_err(&bad)? {
+            checker::Kind::Special(ref exe) => checker::Kind::Special(dir.join(exe).to_string_lossy().into_owned()),
+            kind => kind
+        };
+        let mut cases = Vec::with_capacity(manifest.cases.len());
+        for name in manifest.cases.iter() {
+            let read = |ext: &str| {
+                let path = dir.join(format!("{}.{}", name, ext));
+                fs::read_to_string(&path).map_err(|e| bad(format!("Couldn't read {}: {}", path.display(), e)))
+            };
+            cases.push(Case { name: name.clone(), input: read("in")?, output: read("out")? });
+        }
+        for subtask in manifest.subtasks.iter() {
+            if let Some(missing) = subtask.cases.iter().find(|c| !manifest.cases.contains(c)) {
+                return Err(bad(format!("Subtask {} has unknown case {}", subtask.name, missing)));
+            }
+        }
+        Ok(Problem {
+            max_time:   manifest.time,
+            max_memory: manifest.memory.map(|mb| mb * 1024),
+            checker:    checker,
+            checker_profile: manifest.checker_profile,
+            interactor: manifest.interactor.map(|exe| Interactor {
+                exe:        dir.join(exe).to_string_lossy().into_owned(),
+                max_time:   manifest.interactor_time.or(manifest.time),
+                max_memory: manifest.interactor_memory.map(|mb| mb * 1024),
+                profile:    manifest.interactor_profile
+            }),
+            cases:      cases,
+            subtasks:   manifest.subtasks
+        })
+    }
+}
+
+// Packages under the problems directory, kept in memory once loaded. A package is either the
+// directory `<id>/<version>/` or the archive `<id>/<version>.tar`, which is unpacked next to it.
+pub struct Cache {
+    dir:        PathBuf,
+    loaded:     Mutex<HashMap<String, (u32, Arc<Problem>)>>
+}
+impl Cache {
+    pub fn new(dir: &str) -> Cache {
+        Cache {
+            dir:    PathBuf::from(dir),
+            loaded: Mutex::new(HashMap::new())
+        }
+    }
+    fn unpack(archive: &Path, dir: &Path) -> Result<(), JudgeError> {
+        let bad = |e: String| JudgeError::Problem(archive.display().to_string(), e);
+        // Unpack beside the final directory so a half unpacked package is never loaded. Each
+        // unpack gets its own directory, as other workers or judges may be unpacking it too.
+        let id = UNPACKS.fetch_add(1, Ordering::SeqCst);
+        let partial = dir.with_extension(format!("partial.{}.{}", process::id(), id));
+        if partial.exists() {
+            fs::remove_dir_all(&partial)?;
+        }
+        let file = fs::File::open(archive).map_err(|e| bad(e.to_string()))?;
+        let unpacked = tar::Archive::new(file).unpack(&partial).map_err(|e| bad(e.to_string()))
+            .and_then(|_| match fs::rename(&partial, dir) {
+                // Someone else got there first, with the same package
+                Err(_) if dir.join(MANIFEST).is_file() => Ok(()),
+                renamed => renamed.map_err(JudgeError::from)
+            });
+        if partial.exists() {
+            let _ = fs::remove_dir_all(&partial);
+        }
+        unpacked
+    }
+    // Only the latest version of each problem is kept
+    pub fn get(&self, id: &str, version: u32) -> Result<Arc<Problem>, JudgeError> {
+        if id.is_empty() || id.starts_with('.') || id.contains('/') {
+            return Err(JudgeError::Problem(id.to_owned(), "Not a valid problem id".to_owned()));
+        }
+        if let Some(&(v, ref problem)) = self.loaded.lock().unwrap().get(id) {
+            if v == version {
+                return Ok(problem.clone());
+            }
+        }
+        let dir = self.dir.join(id).join(version.to_string());
+        let archive = self.dir.join(id).join(format!("{}.tar", version));
+        if !dir.join(MANIFEST).is_file() {
+            if !archive.is_file() {
+                return Err(JudgeError::Problem(format!("{} version {}", id, version), "Not in the problems directory".to_owned()));
+            }
+            info!("Unpacking {}", archive.display());
+            Self::unpack(&archive, &dir)?;
+        }
+        let problem = Arc::new(Problem::from_dir(&dir)?);
+        info!("Loaded problem {} version {} with {} cases", id, version, problem.cases.len());
+        let mut loaded = self.loaded.lock().unwrap();
+        match loaded.get(id) {
+            Some(&(v, _)) if v > version => {},
+            _ => { loaded.insert(id.to_owned(), (version, problem.clone())); }
+        }
+        Ok(problem)
+    }
+}
@@ -0,0 +1,105 @@
+extern crate libc;
+
+use super::syscall::{ AUDIT_ARCH_I386, AUDIT_ARCH_X86_64 };
+
+// Not all of these are exposed by libc, so we keep our own copies
+const PR_SET_NO_NEW_PRIVS:  libc::c_int = 38;
+const PR_SET_SECCOMP:       libc::c_int = 22;
+const SECCOMP_MODE_FILTER:  libc::c_ulong = 2;
+
+// Kills every thread in the process, not just the one that made the syscall
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+pub const SECCOMP_RET_ERRNO:        u32 = 0x0005_0000;
+pub const SECCOMP_RET_TRACE:        u32 = 0x7ff0_0000;
+pub const SECCOMP_RET_ALLOW:        u32 = 0x7fff_0000;
+
+// Masks out the data in a return value
+const SECCOMP_RET_ACTION:   u32 = 0xffff_0000;
+
+// Handed to the tracer with SECCOMP_RET_TRACE, saying which ABI the filter matched the syscall
+// against, as the registers alone can't always tell
+pub const TRACE_NATIVE:     u32 = 0;
+pub const TRACE_COMPAT:     u32 = 1;
+pub const TRACE_FOREIGN:    u32 = 2;
+
+pub const PTRACE_O_TRACESECCOMP:    libc::c_int = 0x80;
+pub const PTRACE_EVENT_SECCOMP:     libc::c_int = 7;
+
+const BPF_LD:   u16 = 0x00;
+const BPF_W:    u16 = 0x00;
+const BPF_ABS:  u16 = 0x20;
+const BPF_JMP:  u16 = 0x05;
+const BPF_JA:   u16 = 0x00;
+const BPF_JEQ:  u16 = 0x10;
+const BPF_K:    u16 = 0x00;
+const BPF_RET:  u16 = 0x06;
+
+// Offsets into struct seccomp_data
+const DATA_NR:      u32 = 0;
+const DATA_ARCH:    u32 = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockFilter {
+    code:   u16,
+    jt:     u8,
+    jf:     u8,
+    k:      u32
+}
+
+#[repr(C)]
+struct SockFprog {
+    len:    libc::c_ushort,
+    filter: *const SockFilter
+}
+
+fn stmt(code: u16, k: u32) -> SockFilter {
+    SockFilter { code: code, jt: 0, jf: 0, k: k }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code: code, jt: jt, jf: jf, k: k }
+}
+
+pub struct Filter {
+    program: Vec<SockFilter>
+}
+impl Filter {
+    // Build a filter that returns the given value for each syscall in `rules` and `default` for
+    // everything else. 32-bit syscalls are numbered differently, so they are checked against
+    // `compat` instead. Anything else from another ABI goes to the tracer, which blocks it.
+    pub fn new(rules: &[(u64, u32)], compat: &[(u64, u32)], default: u32) -> Filter {
+        let mut program = vec![
+            stmt(BPF_LD | BPF_W | BPF_ABS, DATA_ARCH),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH_I386, 0, 1),
+            // Filled in once we know where the compat list starts
+            stmt(BPF_JMP | BPF_JA, 0),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH_X86_64, 1, 0),
+            stmt(BPF_RET | BPF_K, SECCOMP_RET_TRACE | TRACE_FOREIGN),
+        ];
+        Self::list(&mut program, rules, default, TRACE_NATIVE);
+        program[2].k = program.len() as u32 - 3;
+        Self::list(&mut program, compat, SECCOMP_RET_TRACE, TRACE_COMPAT);
+        Filter { program: program }
+    }
+    fn list(program: &mut Vec<SockFilter>, rules: &[(u64, u32)], default: u32, arch: u32) {
+        let tag = |ret: u32| if ret & SECCOMP_RET_ACTION == SECCOMP_RET_TRACE { ret | arch } else { ret };
+        program.push(stmt(BPF_LD | BPF_W | BPF_ABS, DATA_NR));
+        for &(syscall, ret) in rules {
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, syscall as u32, 0, 1));
+            program.push(stmt(BPF_RET | BPF_K, tag(ret)));
+        }
+        program.push(stmt(BPF_RET | BPF_K, tag(default)));
+    }
+    // Must only be called in the child, as the filter can never be removed
+    pub fn install(&self) -> bool {
+        let prog = SockFprog {
+            len:    self.program.len() as libc::c_ushort,
+            filter: self.program.as_ptr()
+        };
+        unsafe {
+            libc::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0 &&
+                libc::prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, &prog as *const SockFprog) == 0
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::{ Mutex, atomic::{ AtomicUsize, Ordering } };
+use std::time::{ Duration, Instant };
+
+// What a worker is busy with
+#[derive(Clone, Debug)]
+pub struct Running {
+    pub batch:  u32,
+    pub case:   u32,
+    pub since:  Instant,
+    // How long the case should take at most, if we know
+    pub limit:  Option<Duration>
+}
+
+// Load across every worker, shared with the connection for heartbeats
+pub struct Status {
+    queued:     AtomicUsize,
+    workers:    Mutex<Vec<Option<Running>>>,
+    started:    Instant
+}
+impl Status {
+    pub fn new(workers: usize) -> Status {
+        Status {
+            queued:     AtomicUsize::new(0),
+            workers:    Mutex::new(vec![None; workers]),
+            started:    Instant::now()
+        }
+    }
+    pub fn queue(&self) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+    }
+    pub fn dequeue(&self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+    pub fn start(&self, worker: usize, batch: u32, case: u32, limit: Option<Duration>) {
+        if let Ok(mut workers) = self.workers.lock() {
+            workers[worker] = Some(Running {
+                batch:  batch,
+                case:   case,
+                since:  Instant::now(),
+                limit:  limit
+            });
+        }
+    }
+    pub fn finish(&self, worker: usize) {
+        if let Ok(mut workers) = self.workers.lock() {
+            workers[worker] = None;
+        }
+    }
+    pub fn running(&self) -> Vec<Running> {
+        match self.workers.lock() {
+            Ok(workers) => workers.iter().filter_map(|w| w.clone()).collect(),
+            Err(_) => Vec::new()
+        }
+    }
+    // Cases that have been running far longer than their limit, which means a worker is wedged
+    pub fn stuck(&self, slack: Duration) -> Vec<Running> {
+        self.running().into_iter()
+            .filter(|r| r.since.elapsed() > r.limit.map_or(slack, |l| l * 2 + slack))
+            .collect()
+    }
+}
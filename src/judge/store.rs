@@ -0,0 +1,188 @@
+extern crate libc;
+
+extern crate ring;
+
+use std::collections::{ HashMap, HashSet };
+use std::ffi::CString;
+use std::fs;
+use std::io::{ self, Write };
+use std::os::unix::ffi::OsStrExt;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex };
+use std::time::UNIX_EPOCH;
+
+use super::JudgeError;
+
+// Lowercase hex SHA-256, which is how test files are named in the store and on the wire
+pub fn hash(data: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, data).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+}
+
+struct Entry {
+    size:       u64,
+    // Larger is more recent
+    last_used:  u64
+}
+
+struct Index {
+    entries:    HashMap<String, Entry>,
+    // Hashes that queued submissions still need, which may not have arrived yet
+    pins:       HashMap<String, usize>,
+    used:       u64,
+    clock:      u64
+}
+
+// Test files kept on disk by the hash of their contents. Once the store is over its budget
+// the least recently used files that no submission is waiting on are removed.
+pub struct Store {
+    dir:        PathBuf,
+    budget:     u64,
+    index:      Mutex<Index>
+}
+impl Store {
+    pub fn open(dir: &str, budget: u64) -> io::Result<Store> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let metadata = entry.metadata()?;
+            if !is_hash(&name) || !metadata.is_file() {
+                // Left over from a write that never finished
+                if name.ends_with(".partial") {
+                    let _ = fs::remove_file(entry.path());
+                }
+                continue;
+            }
+            let modified = metadata.modified().ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            found.push((modified, name, metadata.len()));
+        }
+        // Files are touched whenever they're read, so the modification times give the order they were used in
+        found.sort();
+        let mut index = Index { entries: HashMap::new(), pins: HashMap::new(), used: 0, clock: 0 };
+        for (_, name, size) in found {
+            index.clock += 1;
+            index.used += size;
+            index.entries.insert(name, Entry { size: size, last_used: index.clock });
+        }
+        info!("Test data store has {} files using {} MiB", index.entries.len(), index.used / 1024 / 1024);
+        let store = Store { dir: dir, budget: budget, index: Mutex::new(index) };
+        store.evict(&mut store.index.lock().unwrap());
+        Ok(store)
+    }
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash)
+    }
+    fn touch(path: &Path) {
+        if let Ok(path) = CString::new(path.as_os_str().as_bytes()) {
+            unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), ::std::ptr::null(), 0) };
+        }
+    }
+    fn evict(&self, index: &mut Index) {
+        while index.used > self.budget {
+            let oldest = index.entries.iter()
+                .filter(|&(hash, _)| !index.pins.contains_key(hash))
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(hash, _)| hash.clone());
+            let hash = match oldest {
+                Some(hash) => hash,
+                None => {
+                    warn!("Test data store is over budget, but everything in it is in use");
+                    return;
+                }
+            };
+            if let Err(e) = fs::remove_file(self.path(&hash)) {
+                warn!("Couldn't evict {}: {}", hash, e);
+            }
+            if let Some(entry) = index.entries.remove(&hash) {
+                index.used -= entry.size;
+            }
+            debug!("Evicted {} from the test data store", hash);
+        }
+    }
+    pub fn hashes(&self) -> Vec<String> {
+        self.index.lock().unwrap().entries.keys().cloned().collect()
+    }
+    pub fn missing<'a, I: IntoIterator<Item=&'a String>>(&self, hashes: I) -> HashSet<String> {
+        let index = self.index.lock().unwrap();
+        hashes.into_iter().filter(|hash| !index.entries.contains_key(*hash)).cloned().collect()
+    }
+    // Keeps the test data for `cases` from being evicted until the returned guard is dropped
+    pub fn pin(store: &Arc<Store>, cases: Vec<(String, String)>) -> Pinned {
+        {
+            let mut index = store.index.lock().unwrap();
+            for &(ref input, ref output) in cases.iter() {
+                *index.pins.entry(input.clone()).or_insert(0) += 1;
+                *index.pins.entry(output.clone()).or_insert(0) += 1;
+            }
+        }
+        Pinned { store: store.clone(), cases: cases }
+    }
+    // Checks `data` really has the hash the server said it does before keeping it
+    pub fn insert(&self, hash: &str, data: &[u8]) -> Result<(), JudgeError> {
+        if !is_hash(hash) || self::hash(data) != hash {
+            return Err(JudgeError::Protocol(format!("Test data sent as {} doesn't match its hash", hash)));
+        }
+        let path = self.path(hash);
+        let partial = path.with_extension("partial");
+        {
+            let mut file = fs::File::create(&partial)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&partial, &path)?;
+        let mut index = self.index.lock().unwrap();
+        index.clock += 1;
+        let entry = Entry { size: data.len() as u64, last_used: index.clock };
+        index.used += entry.size;
+        if let Some(old) = index.entries.insert(hash.to_owned(), entry) {
+            index.used -= old.size;
+        }
+        self.evict(&mut index);
+        Ok(())
+    }
+    pub fn read(&self, hash: &str) -> Result<String, JudgeError> {
+        let path = self.path(hash);
+        let data = fs::read_to_string(&path)?;
+        Self::touch(&path);
+        let mut index = self.index.lock().unwrap();
+        index.clock += 1;
+        let clock = index.clock;
+        if let Some(entry) = index.entries.get_mut(hash) {
+            entry.last_used = clock;
+        }
+        Ok(data)
+    }
+}
+
+// Input and output hashes for each case of a queued submission
+pub struct Pinned {
+    store:      Arc<Store>,
+    pub cases:  Vec<(String, String)>
+}
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        let mut index = self.store.index.lock().unwrap();
+        for &(ref input, ref output) in self.cases.iter() {
+            for hash in [input, output].iter() {
+                let done = match index.pins.get_mut(*hash) {
+                    Some(count) => {
+                        *count -= 1;
+                        *count == 0
+                    },
+                    None => false
+                };
+                if done {
+                    index.pins.remove(*hash);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+extern crate libc;
+
+use std::mem;
+
+mod i386;
+mod x86_64;
+
+// Newer than the libc crate
+const PTRACE_GET_SYSCALL_INFO:      libc::c_uint = 0x420e;
+const PTRACE_SYSCALL_INFO_ENTRY:    u8 = 1;
+const PTRACE_SYSCALL_INFO_EXIT:     u8 = 2;
+const PTRACE_SYSCALL_INFO_SECCOMP:  u8 = 3;
+
+pub const AUDIT_ARCH_X86_64:    u32 = 0xc000_003e;
+pub const AUDIT_ARCH_I386:      u32 = 0x4000_0003;
+
+// The code segments of a task running 32-bit and 64-bit code on x86_64
+const COMPAT_CS: u64 = 0x23;
+const NATIVE_CS: u64 = 0x33;
+
+// The two byte instructions a 64-bit task can make a syscall with, which leave rip just past them
+const INT_80:   [u8; 2] = [0xcd, 0x80];
+const SYSCALL:  [u8; 2] = [0x0f, 0x05];
+
+const PAGE_SIZE: usize = 4096;
+const PATH_MAX:  usize = 4096;
+
+// The ABI a syscall was made through, which decides what its number means
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arch {
+    X86_64,
+    I386,
+    Other(u32)
+}
+impl Arch {
+    fn from_audit(arch: u32) -> Arch {
+        match arch {
+            AUDIT_ARCH_X86_64 => Arch::X86_64,
+            AUDIT_ARCH_I386 => Arch::I386,
+            x => Arch::Other(x)
+        }
+    }
+    pub fn table(&self) -> &'static [(&'static str, u64)] {
+        match *self {
+            Arch::X86_64 => x86_64::TABLE,
+            Arch::I386 => i386::TABLE,
+            Arch::Other(_) => &[]
+        }
+    }
+    pub fn name(&self, nr: u64) -> Option<&'static str> {
+        self.table().iter().find(|&&(_, n)| n == nr).map(|&(name, _)| name)
+    }
+    pub fn number(&self, name: &str) -> Option<u64> {
+        self.table().iter().find(|&&(n, _)| n == name).map(|&(_, nr)| nr)
+    }
+    // For logging, e.g. "openat" or "i386 socketcall"
+    pub fn describe(&self, nr: u64) -> String {
+        let name = self.name(nr).map_or_else(|| format!("syscall {}", nr), |n| n.to_owned());
+        match *self {
+            Arch::X86_64 => name,
+            Arch::I386 => format!("i386 {}", name),
+            Arch::Other(arch) => format!("{} for arch {:#x}", name, arch)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    Entry {
+        arch:   Arch,
+        nr:     u64,
+        args:   [u64; 6]
+    },
+    Exit {
+        rval:   i64
+    },
+    // An entry we can't tell the ABI of, so can't say which syscall it is
+    Unclassified,
+    // Not a syscall stop at all
+    None
+}
+
+// struct ptrace_syscall_info, where `data` is the union of the entry, exit and seccomp details
+#[repr(C)]
+struct RawInfo {
+    op:                     u8,
+    pad:                    [u8; 3],
+    arch:                   u32,
+    instruction_pointer:    u64,
+    stack_pointer:          u64,
+    data:                   [u64; 8]
+}
+
+// Asks the kernel what the stopped task is doing. Returns None on kernels older than 5.3,
+// which don't have PTRACE_GET_SYSCALL_INFO.
+pub fn info(pid: libc::pid_t) -> Option<Stop> {
+    let mut raw: RawInfo = unsafe { mem::zeroed() };
+    let size = unsafe {
+        libc::ptrace(PTRACE_GET_SYSCALL_INFO, pid, mem::size_of::<RawInfo>(), &mut raw as *mut RawInfo)
+    };
+    if size <= 0 {
+        return None;
+    }
+    let data = raw.data;
+    Some(match raw.op {
+        PTRACE_SYSCALL_INFO_ENTRY | PTRACE_SYSCALL_INFO_SECCOMP => Stop::Entry {
+            arch:   Arch::from_audit(raw.arch),
+            nr:     data[0],
+            args:   [data[1], data[2], data[3], data[4], data[5], data[6]]
+        },
+        PTRACE_SYSCALL_INFO_EXIT => Stop::Exit { rval: data[0] as i64 },
+        _ => Stop::None
+    })
+}
+
+fn regs(pid: libc::pid_t) -> Option<libc::user_regs_struct> {
+    let mut regs: libc::user_regs_struct = unsafe { mem::zeroed() };
+    if unsafe { libc::ptrace(libc::PTRACE_GETREGS, pid, 0, &mut regs) } != 0 {
+        return None;
+    }
+    Some(regs)
+}
+
+fn entry(regs: &libc::user_regs_struct, arch: Arch) -> Stop {
+    let low = |r: u64| r & 0xffff_ffff;
+    match arch {
+        Arch::X86_64 => Stop::Entry {
+            arch:   arch,
+            nr:     regs.orig_rax,
+            args:   [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9]
+        },
+        Arch::I386 => Stop::Entry {
+            arch:   arch,
+            nr:     low(regs.orig_rax),
+            args:   [low(regs.rbx), low(regs.rcx), low(regs.rdx), low(regs.rsi), low(regs.rdi), low(regs.rbp)]
+        },
+        Arch::Other(_) => Stop::Unclassified
+    }
+}
+
+// Reads a syscall entry out of the registers the way `arch` passes them, for when something
+// else, like the seccomp filter, has already told us the ABI
+pub fn regs_as(pid: libc::pid_t, arch: Arch) -> Stop {
+    match regs(pid) {
+        Some(regs) => entry(&regs, arch),
+        None => Stop::None
+    }
+}
+
+// Works the stop out from the registers instead, where only the caller can know whether it's
+// an entry or an exit. The code segment only says what mode the task is in, and 64-bit code can
+// still make 32-bit syscalls with int 0x80, so we look at the instruction that was run too.
+pub fn from_regs(pid: libc::pid_t, entering: bool) -> Stop {
+    let regs = match regs(pid) {
+        Some(regs) => regs,
+        None => return Stop::None
+    };
+    if !entering {
+        return Stop::Exit { rval: regs.rax as i64 };
+    }
+    let arch = match (regs.cs, read_bytes(pid, regs.rip.wrapping_sub(2), 2)) {
+        (COMPAT_CS, _) => Arch::I386,
+        (NATIVE_CS, Some(ref insn)) if insn[..] == INT_80 => Arch::I386,
+        (NATIVE_CS, Some(ref insn)) if insn[..] == SYSCALL => Arch::X86_64,
+        _ => return Stop::Unclassified
+    };
+    entry(&regs, arch)
+}
+
+// Makes the syscall the task is stopped at fail with `errno`. At an entry stop this also skips
+// the syscall, and at an exit stop it replaces whatever it returned.
+pub fn fail(pid: libc::pid_t, errno: u16) {
+    let mut regs: libc::user_regs_struct = unsafe { mem::zeroed() };
+    unsafe {
+        if libc::ptrace(libc::PTRACE_GETREGS, pid, 0, &mut regs) != 0 {
+            return;
+        }
+        regs.orig_rax = u64::max_value();
+        regs.rax = -(errno as i64) as u64;
+        libc::ptrace(libc::PTRACE_SETREGS, pid, 0, &regs);
+    }
+}
+
+fn read_bytes(pid: libc::pid_t, addr: u64, len: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    let local = libc::iovec { iov_base: bytes.as_mut_ptr() as _, iov_len: len };
+    let remote = libc::iovec { iov_base: addr as _, iov_len: len };
+    let read = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+    if read != len as isize {
+        return None;
+    }
+    Some(bytes)
+}
+
+pub fn read_u64(pid: libc::pid_t, addr: u64) -> Option<u64> {
+    read_bytes(pid, addr, 8).map(|bytes| bytes.iter().rev().fold(0, |n, &b| n << 8 | u64::from(b)))
+}
+
+// Reads a path out of the task's memory, a page at a time so we never ask for memory past the
+// end of its mapping
+pub fn read_string(pid: libc::pid_t, mut addr: u64) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    while bytes.len() < PATH_MAX {
+        let mut page = vec![0u8; PAGE_SIZE - addr as usize % PAGE_SIZE];
+        let local = libc::iovec { iov_base: page.as_mut_ptr() as _, iov_len: page.len() };
+        let remote = libc::iovec { iov_base: addr as _, iov_len: page.len() };
+        let read = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+        if read <= 0 {
+            return None;
+        }
+        page.truncate(read as usize);
+        if let Some(end) = page.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&page[..end]);
+            return Some(bytes);
+        }
+        bytes.extend_from_slice(&page);
+        addr += read as u64;
+    }
+    None
+}
@@ -0,0 +1,212 @@
+extern crate clap;
+use clap::ArgMatches;
+
+extern crate futures;
+use futures::Stream;
+
+extern crate pcs_protocol;
+use pcs_protocol::MarkResult;
+
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::error::JudgeError;
+use super::judge;
+
+// How many differing lines to show for each wrong answer
+const DIFF_LINES: usize = 10;
+
+// Pairs up each `<name>.in` in `dir` with its `<name>.out`, numbered cases first and in order
+fn cases(dir: &Path) -> Result<Vec<judge::Case>, JudgeError> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "in"))
+        .collect();
+    let stem = |path: &PathBuf| path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    inputs.sort_by_key(|path| {
+        let name = stem(path);
+        (name.parse::<u64>().is_err(), name.parse::<u64>().unwrap_or(0), name)
+    });
+    let mut cases = Vec::new();
+    for input in inputs {
+        let expected = input.with_extension("out");
+        if !expected.is_file() {
+            warn!("Skipping {} since {} doesn't exist", input.display(), expected.display());
+            continue;
+        }
+        cases.push(judge::Case {
+            name:   stem(&input),
+            input:  fs::read_to_string(&input)?,
+            output: fs::read_to_string(&expected)?
+        });
+    }
+    Ok(cases)
+}
+
+fn verdict(result: &MarkResult) -> String {
+    match *result {
+        MarkResult::Success(_, _) => "Accepted".to_owned(),
+        MarkResult::Fail(_) => "Wrong answer".to_owned(),
+        MarkResult::TLE => "Time limit exceeded".to_owned(),
+        MarkResult::MLE => "Memory limit exceeded".to_owned(),
+        MarkResult::RTE => "Runtime error".to_owned(),
+        MarkResult::CE(_) => "Compile error".to_owned(),
+        MarkResult::IE(_) => "Internal error".to_owned(),
+        MarkResult::Blocked(judge::UNKNOWN_SYSCALL, Some(ref why)) => format!("Blocked syscall ({})", why),
+        MarkResult::Blocked(syscall, Some(ref path)) => format!("Blocked syscall {} on {}", syscall, path),
+        MarkResult::Blocked(syscall, None) => format!("Blocked syscall {}", syscall)
+    }
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+// Lines that differ between the expected and actual output, ignoring trailing whitespace
+fn diff(expected: &str, output: &str) -> Vec<String> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let output: Vec<&str> = output.lines().collect();
+    let mut lines = Vec::new();
+    for i in 0..expected.len().max(output.len()) {
+        let (e, o) = (expected.get(i), output.get(i));
+        if e.map(|e| e.trim_end()) == o.map(|o| o.trim_end()) {
+            continue;
+        }
+        if let Some(e) = e {
+            lines.push(format!("{:>6} - {}", i + 1, e));
+        }
+        if let Some(o) = o {
+            lines.push(format!("{:>6} + {}", i + 1, o));
+        }
+    }
+    lines
+}
+
+// Judges a single submission without a server, printing how each case went. The cases are
+// either a problem package or a directory of loose cases. Returns whether every case was accepted.
+pub fn run(mut settings: judge::Settings, m: &ArgMatches) -> Result<bool, JudgeError> {
+    let source = m.value_of("source").unwrap();
+    let dir = m.value_of("cases").unwrap();
+    let mut problems = Vec::new();
+    let max_time = match m.value_of("time").map(|t| t.parse::<u64>()) {
+        Some(Ok(t)) => Some(t),
+        Some(Err(e)) => {
+            problems.push(format!("--time: {}", e));
+            None
+        },
+        None => None
+    };
+    let max_memory = match m.value_of("memory").map(|t| t.parse::<u64>()) {
+        Some(Ok(mb)) => Some(mb * 1024),
+        Some(Err(e)) => {
+            problems.push(format!("--memory: {}", e));
+            None
+        },
+        None => None
+    };
+    let checker = match m.value_of("checker").map(|c| c.parse::<judge::CheckerKind>()) {
+        Some(Ok(checker)) => Some(checker),
+        Some(Err(e)) => {
+            problems.push(e);
+            None
+        },
+        None => None
+    };
+    let lang = m.value_of("lang").unwrap();
+    if !Path::new(&settings.executors).join(format!("{}.yaml", lang)).is_file() {
+        problems.push(format!("No executor for {} in {}", lang, settings.executors));
+    }
+    if !problems.is_empty() {
+        return Err(JudgeError::Config(problems));
+    }
+
+    let answer = fs::read_to_string(source)?;
+    // Anything given on the command line overrides the package's manifest
+    let mut problem = if Path::new(dir).join(judge::MANIFEST).is_file() {
+        judge::Problem::from_dir(Path::new(dir))?
+    } else {
+        judge::Problem {
+            max_time:   None,
+            max_memory: None,
+            checker:    judge::CheckerKind::default(),
+            checker_profile: None,
+            interactor: None,
+            cases:      cases(Path::new(dir))?,
+            subtasks:   Vec::new()
+        }
+    };
+    if max_time.is_some() {
+        problem.max_time = max_time;
+    }
+    if max_memory.is_some() {
+        problem.max_memory = max_memory;
+    }
+    if let Some(checker) = checker {
+        problem.checker = checker;
+    }
+    if let Some(exe) = m.value_of("interactor") {
+        problem.interactor = Some(judge::Interactor {
+            exe:        exe.to_owned(),
+            max_time:   problem.max_time,
+            max_memory: None,
+            profile:    None
+        });
+    }
+    if problem.cases.is_empty() {
+        return Err(JudgeError::Config(vec![format!("No .in/.out cases in {}", dir)]));
+    }
+    println!("Judging {} against {} cases from {}", source, problem.cases.len(), dir);
+    let problem = Arc::new(problem);
+
+    settings.workers = 1;
+    let (workers, queue, results) = judge::setup(settings)?;
+    queue.send(judge::ToMark {
+        batch:      0,
+        answer:     answer,
+        lang:       lang.to_owned(),
+        problem:    judge::ProblemRef::Inline(problem.clone()),
+        keep_output: true
+    })?;
+    // The worker stops, and so ends the results, once it has marked the only submission
+    drop(queue);
+
+    let mut accepted = 0;
+    for result in results.wait() {
+        let result = result.map_err(|_| JudgeError::Disconnected)?;
+        let case = match result.case {
+            0 => None,
+            n => problem.cases.get(n as usize - 1)
+        };
+        println!("{:<16} {:<24} cpu {:>7.3}s  wall {:>7.3}s", case.map_or("-", |c| c.name.as_str()),
+                 verdict(&result.result), seconds(result.cpu_time), seconds(result.wall_time));
+        match result.result {
+            MarkResult::Success(_, _) => accepted += 1,
+            MarkResult::CE(ref message) | MarkResult::IE(ref message) => {
+                for line in message.lines() {
+                    println!("    {}", line);
+                }
+            },
+            MarkResult::Fail(ref message) => {
+                println!("    {}", message.lines().next().unwrap_or(""));
+                if let (Some(case), Some(output)) = (case, result.output.as_ref()) {
+                    let lines = diff(&case.output, output);
+                    for line in lines.iter().take(DIFF_LINES) {
+                        println!("    {}", line);
+                    }
+                    if lines.len() > DIFF_LINES {
+                        println!("    ... and {} more", lines.len() - DIFF_LINES);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    println!("{}/{} cases accepted", accepted, problem.cases.len());
+    Ok(accepted == problem.cases.len())
+}
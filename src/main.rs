@@ -1,40 +1,134 @@
 extern crate clap;
-use clap::{ Arg, App };
+use clap::{ Arg, App, SubCommand };
 
-extern crate futures;
-use futures::{ Future, Stream };
+extern crate bytes;
+
+#[macro_use] extern crate futures;
+use futures::{ Future, Sink, Stream };
+use futures::sync::mpsc::{ unbounded, UnboundedReceiver };
 
 #[macro_use] extern crate log;
 extern crate pretty_env_logger;
 
 extern crate pcs_protocol;
-use pcs_protocol::*;
+use pcs_protocol::MsgHello;
 
 extern crate rustls;
 
 extern crate tokio_rustls;
-use tokio_rustls::ClientConfigExt;
 
 #[macro_use]
 extern crate serde_derive;
 
 extern crate tokio_core;
-use tokio_core::{ net, reactor };
+use tokio_core::reactor;
 
 extern crate tokio_io;
-use tokio_io::{ AsyncRead };
+use tokio_io::{ AsyncRead, codec::{ FramedRead, FramedWrite }, io::{ ReadHalf, WriteHalf } };
 
 extern crate webpki;
 
+mod codec;
+mod config;
+mod connection;
+mod error;
 mod executor;
+mod fetch;
+mod handshake;
+mod heartbeat;
 mod judge;
+mod local;
+mod outbox;
+mod profile;
 mod ssl;
 mod responses;
 mod tasks;
 
-use std::io;
-use std::net::ToSocketAddrs;
+use std::{ io, process, thread };
 use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use error::JudgeError;
+
+// How often we send heartbeats, and how long the server can be quiet before we reconnect
+struct Liveness {
+    every:      Duration,
+    timeout:    Duration
+}
+
+type Framed = (FramedRead<ReadHalf<connection::Client>, codec::MsgCodec>,
+              FramedWrite<WriteHalf<connection::Client>, codec::MsgCodec>);
+
+// Introduces us over a new connection, telling the server what test data we already have
+fn greet(core: &mut reactor::Core, client: connection::Client, hello: &MsgHello, store: &judge::Store)
+         -> Result<Framed, JudgeError> {
+    let (read, write) = client.split();
+    let mut hello = hello.clone();
+    hello.have = store.hashes();
+    handshake::handshake(core, FramedRead::new(read, codec::MsgCodec::default()),
+                         FramedWrite::new(write, codec::MsgCodec::default()), &hello)
+}
+
+fn session(core: &mut reactor::Core, (read, write): Framed, liveness: &Liveness, to_judge: judge::Queue,
+           from_judge: &mut UnboundedReceiver<judge::ToSend>, outbox: Arc<Mutex<outbox::Outbox>>) -> Result<(), JudgeError> {
+    // Everything for the server is queued here, and written out by the reactor as the socket allows
+    let (outgoing, queued) = unbounded();
+    let writer = write.send_all(queued.map_err(|()| io::Error::new(io::ErrorKind::Other, "Outgoing queue failed")))
+        .map(|_| ());
+
+    { // Anything left over from the last connection goes first
+        let mut outbox = outbox.lock().unwrap();
+        outbox.requeue();
+        if !outbox.is_empty() {
+            info!("Re-sending {} results from the last connection", outbox.len());
+        }
+        outbox.flush(&outgoing)?;
+    }
+
+    let acks = outbox.clone();
+    let judge_socket = tasks::Judge {
+        recv:   from_judge,
+        outbox: outbox,
+        send:   outgoing.clone()
+    };
+    let judge_stream = judge_socket.for_each(move |(mark, outbox, send)| {
+        let mut outbox = outbox.lock().unwrap();
+        outbox.push(mark);
+        outbox.flush(&send)
+    });
+
+    let seen = Arc::new(Mutex::new(Instant::now()));
+    let heartbeat = heartbeat::heartbeat(&core.handle(), liveness.every, liveness.timeout,
+                                         to_judge.status().clone(), seen.clone(), outgoing.clone())?;
+
+    let server_socket = tasks::Server {
+        serv:   outgoing,
+        read:   read,
+        send:   to_judge,
+        seen:   seen
+    };
+    let mut fetches = fetch::Fetches::new();
+    let server_stream = server_socket.for_each(move |(msg, send, judge)| {
+        responses::socket_response(msg, send, judge, &mut fetches, &acks)
+    });
+
+    let streams = judge_stream.select(server_stream).map(|_| ()).map_err(|e| e.0);
+    let streams = streams.select(writer).map(|_| ()).map_err(|e| e.0);
+    core.run(streams.select(heartbeat)).map(|_| ()).map_err(|e| e.0.into())
+}
+
+// Logs every configuration problem on its own line before giving up
+fn configured<T>(loaded: Result<T, JudgeError>) -> Result<T, io::Error> {
+    loaded.map_err(|e| {
+        match e {
+            JudgeError::Config(ref problems) => for problem in problems.iter() {
+                error!("{}", problem);
+            },
+            ref e => error!("{}", e)
+        }
+        e.into()
+    })
+}
 
 fn main() -> Result<(), io::Error> {
     pretty_env_logger::init();
@@ -43,11 +137,21 @@ fn main() -> Result<(), io::Error> {
         .author("Tom Almeida, tommoa256@gmail.com")
         .version("0.1")
         .about("A judge for running programs")
+        .after_help("Every option can also be set with a PCS_JUDGE_<OPTION> environment variable, \
+                     e.g. PCS_JUDGE_SERVER_TIMEOUT, or in the --config file using the long option \
+                     names as keys, e.g. server-timeout: 60. Flags are true or false there, as in \
+                     no-jail: true. Lists are comma separated in the environment.")
+        .arg(Arg::with_name("config")
+             .long("config")
+             .help("YAML file of settings, overridden by the command line and environment")
+             .takes_value(true)
+            )
         .arg(
             Arg::with_name("host")
             .short("h")
             .long("host")
-            .default_value("localhost")
+            .help("[default: localhost]")
+            .takes_value(true)
             )
         .arg(
             Arg::with_name("domain")
@@ -59,84 +163,218 @@ fn main() -> Result<(), io::Error> {
             Arg::with_name("port")
             .short("p")
             .long("port")
-            .default_value("11286")
+            .help("[default: 11286]")
+            .takes_value(true)
             )
-        .arg(Arg::with_name("cert")
+        .arg(Arg::with_name("certificate")
              .short("c")
              .long("certificate")
+             .help("PEM file of CAs to trust, can be given more than once")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+            )
+        .arg(Arg::with_name("system_roots")
+             .long("system-roots")
+             .help("Trust the public web roots as well as the --certificate files")
+            )
+        .arg(Arg::with_name("pin")
+             .long("pin")
+             .help("Only accept a server whose SHA-256 fingerprint is cert:<hex> or spki:<hex>")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+            )
+        .arg(Arg::with_name("client_cert")
+             .long("client-cert")
+             .help("PEM certificate chain to authenticate to the server with")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("client_key")
+             .long("client-key")
+             .help("PEM private key for --client-cert")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("name")
+             .short("n")
+             .long("name")
+             .help("Name to give the server [default: hostname]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("heartbeat")
+             .long("heartbeat")
+             .help("Seconds between heartbeats to the server [default: 10]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("server_timeout")
+             .long("server-timeout")
+             .help("Seconds without hearing from the server before reconnecting [default: 60]")
              .takes_value(true)
             )
         .arg(Arg::with_name("executors")
              .short("e")
              .long("executors")
-             .default_value("executors/")
+             .help("[default: executors/]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("sandbox")
+             .short("s")
+             .long("sandbox")
+             .help("One of ptrace, seccomp or seccomp-kill [default: seccomp]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("wall_multiplier")
+             .short("w")
+             .long("wall-multiplier")
+             .help("Wall clock limit as a multiple of the CPU time limit [default: 3]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("default_wall")
+             .long("default-wall")
+             .help("Wall clock limit in seconds for problems without a time limit [default: 30]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("workers")
+             .short("j")
+             .long("workers")
+             .help("Number of submissions to judge at once [default: physical cores]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("problems")
+             .long("problems")
+             .help("Directory of problem packages, as <id>/<version>/ or <id>/<version>.tar [default: problems/]")
+             .takes_value(true)
             )
+        .arg(Arg::with_name("store")
+             .long("store")
+             .help("Directory to keep test data sent by the server in [default: store/]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("store_budget")
+             .long("store-budget")
+             .help("MiB of test data to keep before removing the least recently used [default: 4096]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("work_dir")
+             .long("work-dir")
+             .help("[default: work/]")
+             .takes_value(true)
+            )
+        .arg(Arg::with_name("no_jail")
+             .long("no-jail")
+             .help("Don't run compilers and submissions in new namespaces with a minimal read-only root. Only for systems without unprivileged user namespaces")
+            )
+        .arg(Arg::with_name("jail_mounts")
+             .long("jail-mounts")
+             .help("Paths to bind mount read-only into the jail, which always has /dev/null, /dev/zero and /dev/urandom")
+             .takes_value(true)
+             .use_delimiter(true)
+            )
+        .subcommand(SubCommand::with_name("local")
+                    .about("Judge a submission against a problem package or a directory of <name>.in and <name>.out cases without a server")
+                    .arg(Arg::with_name("source")
+                         .help("Source file to judge")
+                         .required(true)
+                        )
+                    .arg(Arg::with_name("cases")
+                         .help("Problem package, or directory of cases")
+                         .required(true)
+                        )
+                    .arg(Arg::with_name("lang")
+                         .short("l")
+                         .long("lang")
+                         .help("Language of the source, as named by its executor")
+                         .takes_value(true)
+                         .required(true)
+                        )
+                    .arg(Arg::with_name("time")
+                         .short("t")
+                         .long("time")
+                         .help("CPU time limit in seconds")
+                         .takes_value(true)
+                        )
+                    .arg(Arg::with_name("memory")
+                         .short("m")
+                         .long("memory")
+                         .help("Memory limit in MiB")
+                         .takes_value(true)
+                        )
+                    .arg(Arg::with_name("checker")
+                         .long("checker")
+                         .help("How to compare output, e.g. lines, tokens, float:1e-6 or special:<path> [default: exact]")
+                         .takes_value(true)
+                        )
+                    .arg(Arg::with_name("interactor")
+                         .long("interactor")
+                         .help("Interactor to run the submission against")
+                         .takes_value(true)
+                        )
+                   )
         .get_matches();
 
     debug!("Finished processing arguments");
 
-    let mut core = reactor::Core::new().unwrap();
-    let handle = core.handle();
+    if let Some(local) = m.subcommand_matches("local") {
+        let settings = configured(config::load_settings(&m))?;
+        if !configured(local::run(settings, local))? {
+            process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let server = m.value_of("host").unwrap();
-    let port = m.value_of("port").unwrap().parse().unwrap();
-    let domain = m.value_of("domain").unwrap_or(server);
-    let addr = (server, port)
-        .to_socket_addrs().unwrap()
-        .next().unwrap(); 
+    let options = configured(config::load(&m))?;
 
-    info!("Trying to connect to server!");
-    let connection = net::TcpStream::connect(&addr, &handle);
-    let arc_config = ssl::setup(m.value_of("cert"));
+    let mut core = reactor::Core::new()?;
 
-    let client = connection.map_err(|e| {
-        error!("Error connecting to TCP server {}! {}", addr, e);
+    let host = options.host.as_str();
+    let domain = options.domain.as_str();
+    let client_auth = options.client_auth.as_ref().map(|&(ref cert, ref key)| (cert.as_str(), key.as_str()));
+    let arc_config = ssl::setup(&options.trust, client_auth).map_err(|e| {
+        error!("{}", e);
         e
-    }).and_then(move |stream| {
-        info!("Made TCP connection to {}", server);
-        let domain = webpki::DNSNameRef::try_from_ascii_str(&domain).unwrap();
-        info!("Trying to handshake SSL");
-        arc_config.connect_async(domain, stream)
-    }).wait().map_err(|e| {
-        error!("Error connecting to SSL! {}", e);
-    }).unwrap();
-    info!("SSL connected");
-
-    let (_, to_judge, from_judge) = judge::setup(m.value_of("executors").unwrap().to_string());
-    info!("Started judge thread");
-
-    let fd = {
-        use std::os::unix::io::AsRawFd;
-        client.get_ref().0.as_raw_fd()
-    };
-    let (read, write) = client.split();
-    let arc_client = Arc::new(Mutex::new(write));
-
-    let judge_socket = tasks::Judge {
-        recv: from_judge,
-        send: arc_client.clone()
-    };
-    let judge_stream = judge_socket.for_each(move |(mark, socket)| {
-        use std::ops::DerefMut;
-        use pcs_protocol::SerDe;
-        let socket = socket.clone();
-        let mut socket = socket.lock().unwrap();
-        let mut socket = socket.deref_mut();
-        pcs_protocol::MsgType::Marked(MsgMarked {
-            batch:  mark.batch,
-            case:   mark.case,
-            result: mark.result
-        }).serialize(&mut socket)?;
-        Ok(())
-    });
+    })?;
 
-    let server_socket = tasks::Server {
-        serv:    arc_client.clone(),
-        read:    read,
-        send:    to_judge,
-        recv_fd: fd
+    let name = options.name.clone();
+    let hello = handshake::hello(&name, &options.settings);
+    let liveness = Liveness {
+        every:      options.heartbeat,
+        timeout:    options.server_timeout
     };
-    let server_stream = server_socket.for_each(move |(msg, send, judge)| responses::socket_response(msg, send, judge));
+    let (_, to_judge, mut from_judge) = configured(judge::setup(options.settings))?;
+    info!("Started judge workers");
 
-    core.run(judge_stream.select(server_stream)).map(|_| ()).map_err(|e| e.0)
+    // Results keep queuing in the channel while we're disconnected, and anything the server
+    // didn't acknowledge before a disconnect waits in the outbox
+    let outbox = Arc::new(Mutex::new(outbox::Outbox::new()));
+    let mut backoff = connection::Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    loop {
+        let client = match connection::connect(&mut core, host, options.port, domain, arc_config.clone()) {
+            Ok(client) => client,
+            Err(e) => {
+                let delay = backoff.next();
+                warn!("Couldn't connect to {}:{}: {}. Retrying in {:?}", host, options.port, e, delay);
+                thread::sleep(delay);
+                continue;
+            }
+        };
+        let ended = greet(&mut core, client, &hello, to_judge.store()).and_then(|framed| {
+            // Only once we're accepted, so a server that hangs up straight away isn't retried every second
+            backoff.reset();
+            session(&mut core, framed, &liveness, to_judge.clone(), &mut from_judge, outbox.clone())
+        });
+        match ended {
+            Ok(()) => warn!("Server closed the connection"),
+            Err(JudgeError::Rejected(reason)) => {
+                error!("Server rejected {}: {}", name, reason);
+                return Err(JudgeError::Rejected(reason).into());
+            },
+            Err(e) => error!("Lost connection to the server: {}", e)
+        }
+        if outbox.lock().unwrap().is_disconnected() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Judge thread disconnected"));
+        }
+        let delay = backoff.next();
+        info!("Reconnecting in {:?}", delay);
+        thread::sleep(delay);
+    }
 }
@@ -0,0 +1,82 @@
+extern crate pcs_protocol;
+use pcs_protocol::{ MsgMarked, MsgType };
+
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+
+use super::codec::{ self, Outgoing };
+use super::judge;
+
+fn millis(time: Duration) -> u64 {
+    time.as_secs() * 1000 + u64::from(time.subsec_millis())
+}
+
+// Results from the judge that the server hasn't acknowledged yet.
+// They are keyed by batch and case, so a result that is re-sent after a reconnect
+// replaces any earlier copy rather than being counted twice.
+pub struct Outbox {
+    pending:        BTreeMap<(u32, u32), judge::ToSend>,
+    // Queued for the server, but kept until it acknowledges them in case the connection drops first
+    unacked:        BTreeMap<(u32, u32), judge::ToSend>,
+    disconnected:   bool
+}
+impl Outbox {
+    pub fn new() -> Outbox {
+        Outbox {
+            pending:        BTreeMap::new(),
+            unacked:        BTreeMap::new(),
+            disconnected:   false
+        }
+    }
+    pub fn push(&mut self, result: judge::ToSend) {
+        let key = (result.batch, result.case);
+        self.unacked.remove(&key);
+        self.pending.insert(key, result);
+    }
+    pub fn ack(&mut self, batch: u32, case: u32) {
+        self.unacked.remove(&(batch, case));
+    }
+    // Everything the server never acknowledged has to be sent again on a new connection
+    pub fn requeue(&mut self) {
+        for (key, result) in ::std::mem::replace(&mut self.unacked, BTreeMap::new()) {
+            self.pending.entry(key).or_insert(result);
+        }
+    }
+    pub fn disconnect(&mut self) {
+        self.disconnected = true;
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+    // Queue every pending result for the server, holding on to each until it's acknowledged
+    pub fn flush(&mut self, out: &Outgoing) -> Result<(), io::Error> {
+        let keys: Vec<(u32, u32)> = self.pending.keys().cloned().collect();
+        for key in keys {
+            let mark = self.pending[&key].clone();
+            codec::queue(out, MsgType::Marked(MsgMarked {
+                batch:      mark.batch,
+                case:       mark.case,
+                result:     mark.result,
+                // In milliseconds
+                cpu_time:   millis(mark.cpu_time),
+                wall_time:  millis(mark.wall_time)
+            }))?;
+            if let Some(mark) = self.pending.remove(&key) {
+                self.unacked.insert(key, mark);
+            }
+        }
+        Ok(())
+    }
+}
+impl Default for Outbox {
+    fn default() -> Outbox {
+        Outbox::new()
+    }
+}
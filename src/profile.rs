@@ -0,0 +1,73 @@
+extern crate serde_yaml;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use super::error::JudgeError;
+use super::judge;
+
+// Profiles live in this directory inside the executors directory, as `<name>.yaml`
+pub const DIR: &str = "profiles";
+
+// How a kind of runtime has to be sandboxed, shared between the executors that use it.
+// A profile can extend another, in which case anything it sets wins over the other's.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub extends:            Option<String>,
+    // Added to the standard syscalls, as with an executor's own `syscalls`
+    #[serde(default)]
+    pub syscalls:           BTreeMap<String, judge::Action>,
+    #[serde(default)]
+    pub compat_syscalls:    BTreeMap<String, judge::Action>,
+    #[serde(default)]
+    pub read_paths:         Vec<String>,
+    // How many threads and processes may be running at once, including the first
+    pub threads:            Option<usize>,
+    // Whether the memory limit caps virtual memory. Runtimes that reserve far more than they use,
+    // like the JVM, need this off, which caps only the private writable memory they map.
+    pub address_space:      Option<bool>,
+    // Added to the problem's memory limit in MiB, to cover the runtime itself
+    pub extra_memory:       Option<u64>
+}
+impl Profile {
+    fn from_file(path: &Path) -> Result<Profile, JudgeError> {
+        let describe = |e: &dyn ToString| JudgeError::Profile(path.display().to_string(), e.to_string());
+        let file = File::open(path).map_err(|e| describe(&e))?;
+        serde_yaml::from_reader(file).map_err(|e| describe(&e))
+    }
+    // Lays `self` over `base`, so anything set here wins
+    pub fn over(self, base: Profile) -> Profile {
+        let mut syscalls = base.syscalls;
+        syscalls.extend(self.syscalls);
+        let mut compat_syscalls = base.compat_syscalls;
+        compat_syscalls.extend(self.compat_syscalls);
+        let mut read_paths = base.read_paths;
+        read_paths.extend(self.read_paths);
+        Profile {
+            extends:            None,
+            syscalls:           syscalls,
+            compat_syscalls:    compat_syscalls,
+            read_paths:         read_paths,
+            threads:            self.threads.or(base.threads),
+            address_space:      self.address_space.or(base.address_space),
+            extra_memory:       self.extra_memory.or(base.extra_memory)
+        }
+    }
+    // Loads the profile `name` from `dir` along with everything it extends
+    pub fn load(dir: &Path, name: &str) -> Result<Profile, JudgeError> {
+        let mut chain = Vec::new();
+        let mut next = Some(name.to_owned());
+        while let Some(name) = next {
+            let path = dir.join(format!("{}.yaml", name));
+            if name.is_empty() || name.contains('/') || chain.iter().any(|&(ref n, _)| *n == name) {
+                return Err(JudgeError::Profile(path.display().to_string(), "Profiles can't extend themselves".to_owned()));
+            }
+            let profile = Self::from_file(&path)?;
+            next = profile.extends.clone();
+            chain.push((name, profile));
+        }
+        Ok(chain.into_iter().rev().fold(Profile::default(), |base, (_, profile)| profile.over(base)))
+    }
+}
@@ -1,14 +1,155 @@
 extern crate pcs_protocol;
+use pcs_protocol::{ MarkResult, MsgType };
 
 use std::io;
-use std::sync::{ Arc, mpsc, Mutex };
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
 
+use super::codec::{ self, Outgoing };
+use super::fetch::Fetches;
 use super::judge;
+use super::outbox::Outbox;
 
-pub fn socket_response<W: io::Write>(
-    _msg: pcs_protocol::MsgType,
-    _write: Arc<Mutex<W>>,
-    _judge: mpsc::Sender<judge::ToMark>) -> Result<(), io::Error>
+fn reply(write: &Outgoing, msg: MsgType) -> Result<(), io::Error> {
+    codec::queue(write, msg)
+}
+
+// Gives up on a batch with an internal error, so the server isn't left waiting on it
+fn abandon(write: &Outgoing, outbox: &Arc<Mutex<Outbox>>, batch: u32, why: String) -> Result<(), io::Error> {
+    let mut outbox = outbox.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Outbox lock poisoned"))?;
+    outbox.push(judge::ToSend {
+        batch:      batch,
+        case:       0,
+        result:     MarkResult::IE(why),
+        cpu_time:   Duration::default(),
+        wall_time:  Duration::default(),
+        output:     None
+    });
+    outbox.flush(write)
+}
+
+pub fn socket_response(
+    msg: MsgType,
+    write: Outgoing,
+    to_judge: judge::Queue,
+    fetches: &mut Fetches,
+    outbox: &Arc<Mutex<Outbox>>) -> Result<(), io::Error>
 {
-    Ok(())
+    match msg {
+        MsgType::Mark(mark) => {
+            // Newer servers only name the problem, older ones send everything with each submission
+            let problem = match mark.problem {
+                Some((id, version)) => {
+                    debug!("Received batch {} ({}) for problem {} version {}", mark.batch, mark.lang, id, version);
+                    judge::ProblemRef::Package(id, version)
+                },
+                None => {
+                    debug!("Received batch {} ({}) with {} cases", mark.batch, mark.lang,
+                           mark.data.as_ref().map_or(mark.case_in.len(), |d| d.len()));
+                    let checker = match mark.checker.parse::<judge::CheckerKind>() {
+                        Ok(checker) => checker,
+                        Err(e) => {
+                            error!("Batch {} asked for a bad checker: {}", mark.batch, e);
+                            return abandon(&write, outbox, mark.batch, e);
+                        }
+                    };
+                    let (interactor_time, interactor_memory) = (mark.interactor_time.or(mark.max_time), mark.interactor_memory);
+                    let mut problem = judge::Problem {
+                        max_time:   mark.max_time,
+                        max_memory: mark.max_memory,
+                        checker:    checker,
+                        checker_profile: None,
+                        interactor: mark.interactor.map(|exe| judge::Interactor {
+                            exe:        exe,
+                            max_time:   interactor_time,
+                            max_memory: interactor_memory,
+                            profile:    None
+                        }),
+                        cases:      Vec::new(),
+                        subtasks:   Vec::new()
+                    };
+                    match mark.data {
+                        // The test data is named by hash, and may have to be fetched first
+                        Some(hashes) => {
+                            let pinned = judge::Store::pin(to_judge.store(), hashes);
+                            judge::ProblemRef::Stored(Arc::new(problem), pinned)
+                        },
+                        None => {
+                            problem.cases = mark.case_in.into_iter().zip(mark.case_out.into_iter()).enumerate()
+                                .map(|(i, (input, output))| judge::Case { name: (i + 1).to_string(), input: input, output: output })
+                                .collect();
+                            judge::ProblemRef::Inline(Arc::new(problem))
+                        }
+                    }
+                }
+            };
+            let missing = match problem {
+                judge::ProblemRef::Stored(_, ref pinned) => {
+                    to_judge.store().missing(pinned.cases.iter().flat_map(|&(ref i, ref o)| vec![i, o]))
+                },
+                _ => Default::default()
+            };
+            let to_mark = judge::ToMark {
+                batch:      mark.batch,
+                answer:     mark.answer,
+                lang:       mark.lang,
+                problem:    problem,
+                keep_output: false
+            };
+            if missing.is_empty() {
+                return to_judge.send(to_mark).map_err(io::Error::from);
+            }
+            debug!("Batch {} is waiting on {} test files", to_mark.batch, missing.len());
+            let ask = fetches.wait(to_mark, missing);
+            if ask.is_empty() {
+                return Ok(());
+            }
+            reply(&write, MsgType::Fetch(ask))
+        },
+        MsgType::Data(hash, data) => {
+            match to_judge.store().insert(&hash, &data) {
+                Ok(()) => {
+                    trace!("Stored test data {}", hash);
+                    for mark in fetches.arrived(&hash) {
+                        to_judge.send(mark)?;
+                    }
+                    Ok(())
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    for batch in fetches.failed(&hash) {
+                        error!("Dropping batch {} since its test data is bad", batch);
+                        abandon(&write, outbox, batch, format!("Bad test data {}: {}", hash, e))?;
+                    }
+                    reply(&write, MsgType::Error(e.to_string()))
+                }
+            }
+        },
+        MsgType::Ack(batch, case) => {
+            trace!("Server has the result for batch {} case {}", batch, case);
+            if let Ok(mut outbox) = outbox.lock() {
+                outbox.ack(batch, case);
+            }
+            Ok(())
+        },
+        MsgType::Ping => {
+            trace!("Ping from server");
+            reply(&write, MsgType::Pong)
+        },
+        MsgType::Pong => Ok(()),
+        // Just hearing from the server is enough to know it's alive
+        MsgType::Heartbeat(_) => Ok(()),
+        MsgType::Close => {
+            info!("Server closed the connection");
+            Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Server closed the connection"))
+        },
+        MsgType::Error(e) => {
+            error!("Server reported an error: {}", e);
+            Ok(())
+        },
+        x => {
+            warn!("Received unexpected message from server: {:?}", x);
+            reply(&write, MsgType::Error(format!("Judge cannot handle {:?}", x)))
+        }
+    }
 }
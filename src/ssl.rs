@@ -1,22 +1,242 @@
+extern crate ring;
+
 extern crate rustls;
+use rustls::{ Certificate, PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier, SignatureScheme, TLSError };
+use rustls::internal::pemfile;
+use rustls::sign::SigningKey;
+
+extern crate untrusted;
 
 extern crate webpki;
 
 extern crate webpki_roots;
 
 use std::sync::Arc;
-use std::fs;
-use std::io::BufReader;
+use std::{ fs, str };
+use std::str::FromStr;
+use std::io::{ BufReader, Seek, SeekFrom };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use super::error::JudgeError;
+
+fn open(path: &str) -> Result<BufReader<fs::File>, JudgeError> {
+    fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| JudgeError::Certificate(format!("Couldn't open {}: {}", path, e)))
+}
+
+// Reads one DER tag-length-value, returning the tag, its contents and whatever follows it
+fn der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let len = input[..n].iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+        input = &input[n..];
+        len
+    };
+    if input.len() < len {
+        return None;
+    }
+    Some((tag, &input[..len], &input[len..]))
+}
+
+// Seconds since the epoch from an ASN.1 UTCTime or GeneralizedTime
+fn parse_time(tag: u8, time: &[u8]) -> Option<u64> {
+    let s = str::from_utf8(time).ok()?;
+    let (year, rest): (i64, &str) = match tag {
+        0x17 => {
+            let yy: i64 = s.get(0..2)?.parse().ok()?;
+            (if yy >= 50 { 1900 + yy } else { 2000 + yy }, s.get(2..)?)
+        },
+        0x18 => (s.get(0..4)?.parse().ok()?, s.get(4..)?),
+        _ => return None
+    };
+    let field = |i: usize| -> Option<i64> { rest.get(i..i + 2)?.parse().ok() };
+    let (month, day, hour, min, sec) = (field(0)?, field(2)?, field(4)?, field(6)?, field(8)?);
+    // Days since 1970-01-01, using Howard Hinnant's days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some((days * 86400 + hour * 3600 + min * 60 + sec) as u64)
+}
+
+// The validity period and the whole encoded SubjectPublicKeyInfo of a certificate
+fn tbs_fields(cert: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (_, cert, _) = der(cert)?;
+    let (_, tbs, _) = der(cert)?;
+    // The version is optional, so the serial number is either first or second
+    let (tag, _, mut rest) = der(tbs)?;
+    if tag == 0xa0 {
+        rest = der(rest)?.2;
+    }
+    let rest = der(rest)?.2; // signature
+    let rest = der(rest)?.2; // issuer
+    let (_, validity, rest) = der(rest)?;
+    let rest = der(rest)?.2; // subject
+    let after = der(rest)?.2;
+    Some((validity, &rest[..rest.len() - after.len()]))
+}
+
+// The notAfter time of a certificate
+fn not_after(cert: &[u8]) -> Option<u64> {
+    let (validity, _) = tbs_fields(cert)?;
+    let validity = der(validity)?.2; // notBefore
+    let (tag, time, _) = der(validity)?;
+    parse_time(tag, time)
+}
+
+fn sha256(bytes: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, bytes).as_ref().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+// A SHA-256 fingerprint the server's certificate has to match, either of the whole
+// certificate or of just its public key (which survives the certificate being reissued)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pin {
+    Cert(Vec<u8>),
+    Spki(Vec<u8>)
+}
+impl Pin {
+    fn matches(&self, cert: &Certificate) -> bool {
+        match *self {
+            Pin::Cert(ref hash) => &sha256(&cert.0) == hash,
+            Pin::Spki(ref hash) => tbs_fields(&cert.0).map(|(_, spki)| &sha256(spki) == hash).unwrap_or(false)
+        }
+    }
+}
+// Parses "cert:<hex>" or "spki:<hex>", where the hex may be separated by colons like
+// `openssl x509 -fingerprint -sha256` prints it
+impl FromStr for Pin {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Pin, String> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let digits: String = parts.next().unwrap_or("").chars().filter(|&c| c != ':').collect();
+        if digits.len() != 64 || !digits.chars().all(|c| c.is_digit(16)) {
+            return Err(format!("Pin {} isn't a SHA-256 fingerprint", s));
+        }
+        let hash = (0..32).map(|i| u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).unwrap()).collect();
+        match kind {
+            "cert" => Ok(Pin::Cert(hash)),
+            "spki" => Ok(Pin::Spki(hash)),
+            _ => Err(format!("Pin {} should start with cert: or spki:", s))
+        }
+    }
+}
+
+// Accepts exactly the servers whose certificate matches a pin, whoever signed it
+struct Pinned {
+    pins: Vec<Pin>
+}
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(&self, _roots: &RootCertStore, presented: &[Certificate],
+                          _dns_name: webpki::DNSNameRef, _ocsp: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let cert = presented.first().ok_or(TLSError::NoCertificatesPresented)?;
+        if self.pins.iter().any(|pin| pin.matches(cert)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            let spki = tbs_fields(&cert.0).map(|(_, spki)| hex(&sha256(spki))).unwrap_or_default();
+            error!("Server certificate doesn't match any pin (cert:{}, spki:{})", hex(&sha256(&cert.0)), spki);
+            Err(TLSError::General("Server certificate doesn't match any pin".to_owned()))
+        }
+    }
+}
+
+// Which servers we're willing to talk to. Without any certificate files the public web
+// roots are used, and pins replace the usual chain verification altogether.
+pub struct Trust {
+    pub certs:          Vec<String>,
+    pub system_roots:   bool,
+    pub pins:           Vec<Pin>
+}
 
-pub fn setup(cert: Option<&str>) -> Arc<rustls::ClientConfig> {
+fn private_key(path: &str) -> Result<PrivateKey, JudgeError> {
+    let mut pem = open(path)?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut pem).unwrap_or_default();
+    if keys.is_empty() {
+        pem.seek(SeekFrom::Start(0)).map_err(|e| JudgeError::Certificate(format!("Couldn't read {}: {}", path, e)))?;
+        keys = pemfile::rsa_private_keys(&mut pem).unwrap_or_default();
+    }
+    match keys.len() {
+        0 => Err(JudgeError::Certificate(format!("No RSA private key in {}", path))),
+        1 => Ok(keys.remove(0)),
+        n => {
+            warn!("{} has {} private keys, using the first", path, n);
+            Ok(keys.remove(0))
+        }
+    }
+}
+
+// Prove that the key belongs to the certificate by signing with one and verifying with the other
+fn check_pair(cert: &Certificate, key: &PrivateKey, cert_path: &str, key_path: &str) -> Result<(), JudgeError> {
+    let mismatch = || JudgeError::Certificate(format!("Private key {} doesn't match certificate {}", key_path, cert_path));
+    let signing = rustls::sign::RSASigningKey::new(key)
+        .map_err(|_| JudgeError::Certificate(format!("{} isn't a usable RSA key", key_path)))?;
+    let signer = signing.choose_scheme(&[SignatureScheme::RSA_PKCS1_SHA256]).ok_or_else(&mismatch)?;
+    let message = b"pcs_judge client certificate check";
+    let signature = signer.sign(message).map_err(|_| mismatch())?;
+    let end_entity = webpki::EndEntityCert::from(untrusted::Input::from(&cert.0))
+        .map_err(|e| JudgeError::Certificate(format!("Couldn't parse {}: {:?}", cert_path, e)))?;
+    end_entity.verify_signature(&webpki::RSA_PKCS1_2048_8192_SHA256,
+                                untrusted::Input::from(message), untrusted::Input::from(&signature))
+        .map_err(|_| mismatch())
+}
+
+fn client_auth(config: &mut rustls::ClientConfig, cert_path: &str, key_path: &str) -> Result<(), JudgeError> {
+    let chain = pemfile::certs(&mut open(cert_path)?).unwrap_or_default();
+    if chain.is_empty() {
+        return Err(JudgeError::Certificate(format!("No certificates in {}", cert_path)));
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    match not_after(&chain[0].0) {
+        Some(expiry) if expiry < now => {
+            return Err(JudgeError::Certificate(format!("Client certificate {} expired {} days ago",
+                                                       cert_path, (now - expiry) / 86400)));
+        },
+        Some(_) => {},
+        None => warn!("Couldn't read the expiry date of {}", cert_path)
+    }
+    let key = private_key(key_path)?;
+    check_pair(&chain[0], &key, cert_path, key_path)?;
+    info!("Using {} as client certificate", cert_path);
+    config.set_single_client_cert(chain, key);
+    Ok(())
+}
+
+pub fn setup(trust: &Trust, client: Option<(&str, &str)>) -> Result<Arc<rustls::ClientConfig>, JudgeError> {
     let mut config = rustls::ClientConfig::new();
-    if let Some(cert) = cert {
+    for cert in trust.certs.iter() {
         info!("Using {} as cert file", cert);
-        let mut pem = BufReader::new(fs::File::open(cert).unwrap());
-        config.root_store.add_pem_file(&mut pem).unwrap();
-    } else {
+        let mut pem = open(cert)?;
+        match config.root_store.add_pem_file(&mut pem) {
+            Ok((0, _)) | Err(_) => return Err(JudgeError::Certificate(format!("No valid certificates in {}", cert))),
+            Ok((_, 0)) => {},
+            Ok((_, invalid)) => warn!("Skipped {} invalid certificates in {}", invalid, cert)
+        }
+    }
+    if trust.certs.is_empty() || trust.system_roots {
         info!("Using TLS server roots for cert");
         config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
     }
-    Arc::new(config)
+    if !trust.pins.is_empty() {
+        info!("Pinning the server to {} fingerprints", trust.pins.len());
+        config.dangerous().set_certificate_verifier(Arc::new(Pinned { pins: trust.pins.clone() }));
+    }
+    if let Some((cert, key)) = client {
+        client_auth(&mut config, cert, key)?;
+    }
+    Ok(Arc::new(config))
 }
@@ -1,58 +1,71 @@
 extern crate pcs_protocol;
-use pcs_protocol::{ MsgType, SerDe };
+use pcs_protocol::MsgType;
 
 extern crate futures;
 use futures::prelude::*;
+use futures::sync::mpsc::UnboundedReceiver;
 
-extern crate libc;
+extern crate tokio_io;
+use tokio_io::{ AsyncRead, codec::FramedRead };
 
-use std::sync::{ Arc, mpsc, Mutex };
-use std::io::{ self, Read, Write };
-use super::judge;
+use std::sync::{ Arc, Mutex };
+use std::time::Instant;
+use std::io;
+use super::{ judge, outbox::Outbox };
+use super::codec::{ self, Frame, MsgCodec, Outgoing };
 
-pub struct Judge<W> {
-    pub recv: mpsc::Receiver<judge::ToSend>,
-    pub send: Arc<Mutex<W>>,
+// Results from the judge, woken by the channel whenever a worker finishes a case.
+// The receiver is borrowed so that it outlives any one connection.
+pub struct Judge<'a> {
+    pub recv:   &'a mut UnboundedReceiver<judge::ToSend>,
+    pub outbox: Arc<Mutex<Outbox>>,
+    pub send:   Outgoing,
 }
-impl<W: Write> Stream for Judge<W> {
-    type Item = (judge::ToSend, Arc<Mutex<W>>);
+impl<'a> Stream for Judge<'a> {
+    type Item = (judge::ToSend, Arc<Mutex<Outbox>>, Outgoing);
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
-        match self.recv.try_recv() {
-            Ok(value) => Ok(Async::Ready(Some((value, self.send.clone())))),
-            Err(mpsc::TryRecvError::Empty) => Ok(Async::NotReady),
-            Err(mpsc::TryRecvError::Disconnected) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "Judge thread disconnected"))
+        match self.recv.poll() {
+            Ok(Async::Ready(Some(value))) => Ok(Async::Ready(Some((value, self.outbox.clone(), self.send.clone())))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(None)) | Err(_) => {
+                if let Ok(mut outbox) = self.outbox.lock() {
+                    outbox.disconnect();
+                }
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "Judge thread disconnected"))
+            }
         }
     }
 }
 
-pub struct Server<R, W> {
-    pub serv:    Arc<Mutex<W>>,
-    pub read:    R,
-    pub send:    mpsc::Sender<judge::ToMark>,
-    pub recv_fd: i32
+// Messages from the server, woken by the reactor whenever the socket is readable
+pub struct Server<R> {
+    pub serv:   Outgoing,
+    pub read:   FramedRead<R, MsgCodec>,
+    pub send:   judge::Queue,
+    // When we last heard anything from the server
+    pub seen:   Arc<Mutex<Instant>>
 }
-impl<R: Read, W: Write> Stream for Server<R, W> {
-    type Item = (MsgType, Arc<Mutex<W>>, mpsc::Sender<judge::ToMark>);
+impl<R: AsyncRead> Stream for Server<R> {
+    type Item = (MsgType, Outgoing, judge::Queue);
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
-        use std::mem;
-        let mut pfd: libc::pollfd = unsafe { mem::zeroed() };
-        pfd.fd = self.recv_fd;
-        pfd.events |= libc::POLLIN;
-        unsafe { libc::poll(&mut pfd, 1, 0) };
-        if pfd.revents & libc::POLLIN > 0 {
-            Ok(Async::Ready(Some((MsgType::deserialize(&mut self.read)?, self.serv.clone(), self.send.clone()))))
-        } else if pfd.revents & libc::POLLHUP > 0 {
-            Ok(Async::Ready(None))
-        } else if pfd.revents & libc::POLLERR > 0 {
-            Err(io::Error::new(io::ErrorKind::BrokenPipe, "POLLERR"))
-        } else if pfd.revents & libc::POLLNVAL > 0 {
-            Err(io::Error::new(io::ErrorKind::InvalidData, "POLLNVAL"))
-        } else {
-            Ok(Async::NotReady)
+        loop {
+            let frame = try_ready!(self.read.poll());
+            if let Ok(mut seen) = self.seen.lock() {
+                *seen = Instant::now();
+            }
+            match frame {
+                Some(Frame::Msg(msg)) => return Ok(Async::Ready(Some((msg, self.serv.clone(), self.send.clone())))),
+                Some(Frame::Corrupt(e)) => {
+                    // The length prefix keeps us in sync, so just tell the server and carry on
+                    warn!("Dropped a corrupt frame from the server: {}", e);
+                    codec::queue(&self.serv, MsgType::Error(e))?;
+                },
+                None => return Ok(Async::Ready(None))
+            }
         }
     }
 }